@@ -0,0 +1,79 @@
+// throughput of the sequential engine against the sharded one, run with `cargo bench`
+// both read the same generated csv from memory, so parsing is part of the measurement as it is for the binary
+
+use std::time::{Duration, Instant};
+
+use oxidized_transactions::{Engine, ParseMode, ShardedEngine, TransactionReader};
+
+const ROWS: u32 = 1_000_000;
+const CLIENTS: u32 = 10_000;
+
+// deposits, withdrawals and a few disputes spread over many clients, deterministic so runs compare
+fn generate() -> String {
+    let mut csv = String::from("type,client,tx,amount\n");
+    let mut seed: u32 = 0x2545_f491;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+    for tx in 1..=ROWS {
+        let client = next() % CLIENTS + 1;
+        let amount = next();
+        let row = match next() % 10 {
+            0..=5 => format!("deposit,{},{},{}.{:04}\n", client, tx, amount % 1000, amount % 10_000),
+            6..=8 => format!("withdrawal,{},{},{}.5\n", client, tx, amount % 100),
+            _ => format!("dispute,{},{},\n", client, tx / 2 + 1),
+        };
+        csv.push_str(&row);
+    }
+    csv
+}
+
+fn sequential(input: &str) -> Engine {
+    let mut engine = Engine::new();
+    for row in TransactionReader::new(input.as_bytes(), ParseMode::Lenient).unwrap() {
+        if let Ok(transaction) = row.unwrap().transaction {
+            let _ = engine.apply(transaction);
+        }
+        // as the binary does, so events do not pile up
+        engine.take_ledger();
+    }
+    engine
+}
+
+fn sharded(input: &str, shards: usize) -> Engine {
+    let mut engine = ShardedEngine::new(Engine::new(), shards);
+    for row in TransactionReader::new(input.as_bytes(), ParseMode::Lenient).unwrap() {
+        let row = row.unwrap();
+        if let Ok(transaction) = row.transaction {
+            let _ = engine.submit(row.line, transaction);
+        }
+    }
+    engine.finish().0
+}
+
+// best of three, to keep one slow run from skewing the result
+fn measure<F: Fn() -> Engine>(name: &str, run: F) {
+    let mut best = Duration::MAX;
+    let mut accounts = 0;
+    for _ in 0..3 {
+        let start = Instant::now();
+        let engine = run();
+        best = best.min(start.elapsed());
+        accounts = engine.accounts().count();
+    }
+
+    let rows_per_sec = f64::from(ROWS) / best.as_secs_f64();
+    println!("{:<12} {:>8.0} ms {:>12.0} rows/s ({} accounts)", name, best.as_secs_f64() * 1000.0, rows_per_sec, accounts);
+}
+
+fn main() {
+    let input = generate();
+
+    measure("sequential", || sequential(&input));
+    for shards in [1, 2, 4, 8] {
+        measure(&format!("{} shards", shards), || sharded(&input, shards));
+    }
+}
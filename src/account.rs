@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+use crate::amount::Amount;
+
+// latest balances for a single client
+// total is always available + held
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// number of decimal places an amount is tracked to
+pub const DECIMALS: u32 = 4;
+
+// 10^DECIMALS, the number of units in 1.0000
+const SCALE: i64 = 10_i64.pow(DECIMALS);
+
+// Amount is a fixed-point number with exactly four decimal places
+// the value is stored as a count of 0.0001 units so arithmetic is exact
+// all arithmetic is checked, callers get None back instead of a wrapped value on overflow
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseAmountError {
+    Empty,
+    InvalidDigit,
+    TooManyDecimals,
+    Overflow,
+}
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    pub fn checked_neg(self) -> Option<Amount> {
+        self.0.checked_neg().map(Amount)
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    // percent of the amount, e.g. 1.5 percent of 10.0 is 0.15
+    // the result is cut off after four decimal places, toward zero
+    pub fn checked_percent(self, percent: Amount) -> Option<Amount> {
+        let units = i128::from(self.0) * i128::from(percent.0) / (100 * i128::from(SCALE));
+        i64::try_from(units).ok().map(Amount)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // accepts an optional sign, digits and up to four decimal places, e.g. "-12.5", "3", ".25"
+        let s = s.trim();
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+
+        let (whole, fraction) = match unsigned.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (unsigned, ""),
+        };
+
+        if whole.is_empty() && fraction.is_empty() {
+            return Err(ParseAmountError::Empty);
+        }
+
+        if !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+            return Err(ParseAmountError::InvalidDigit);
+        }
+
+        if fraction.len() > DECIMALS as usize {
+            return Err(ParseAmountError::TooManyDecimals);
+        }
+
+        let mut units: i64 = 0;
+        for digit in whole.bytes() {
+            units = units
+                .checked_mul(10)
+                .and_then(|u| u.checked_add(i64::from(digit - b'0')))
+                .ok_or(ParseAmountError::Overflow)?;
+        }
+        units = units.checked_mul(SCALE).ok_or(ParseAmountError::Overflow)?;
+
+        // pad the fraction out to four places, "5" is 5000 units
+        let mut fraction_units: i64 = 0;
+        for place in 0..DECIMALS as usize {
+            let digit = fraction.as_bytes().get(place).map_or(0, |d| i64::from(d - b'0'));
+            fraction_units = fraction_units * 10 + digit;
+        }
+        units = units.checked_add(fraction_units).ok_or(ParseAmountError::Overflow)?;
+
+        Ok(Amount(if negative { -units } else { units }))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // always prints exactly four decimal places
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+        let scale = SCALE as u64;
+        write!(f, "{}{}.{:04}", sign, units / scale, units % scale)
+    }
+}
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseAmountError::Empty => write!(f, "amount is empty"),
+            ParseAmountError::InvalidDigit => write!(f, "amount contains an invalid digit"),
+            ParseAmountError::TooManyDecimals => write!(f, "amount has more than {} decimal places", DECIMALS),
+            ParseAmountError::Overflow => write!(f, "amount is too large"),
+        }
+    }
+}
+
+impl std::error::Error for ParseAmountError {}
+
+struct AmountVisitor;
+
+impl<'de> Visitor<'de> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a decimal amount with at most {} decimal places", DECIMALS)
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(AmountVisitor)
+    }
+}
+
+// serialized as the four decimal place string so json consumers never see a float
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+// lenient stand-in for serde_with::DefaultOnError on amount fields
+// DefaultOnError buffers the field first, which makes csv guess its type and turn amounts into f64
+// reading the raw text keeps large amounts exact, anything that does not parse is treated as missing
+pub fn deserialize_or_none<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Amount>, D::Error> {
+    let raw = String::deserialize(deserializer)?;
+    Ok(raw.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_whole_and_fractional() {
+        assert_eq!("1".parse::<Amount>().unwrap(), Amount(10_000));
+        assert_eq!("1.5".parse::<Amount>().unwrap(), Amount(15_000));
+        assert_eq!(" 2.0001 ".parse::<Amount>().unwrap(), Amount(20_001));
+        assert_eq!(".25".parse::<Amount>().unwrap(), Amount(2_500));
+        assert_eq!("-3.1".parse::<Amount>().unwrap(), Amount(-31_000));
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid() {
+        assert_eq!("".parse::<Amount>(), Err(ParseAmountError::Empty));
+        assert_eq!(".".parse::<Amount>(), Err(ParseAmountError::Empty));
+        assert_eq!("1.00001".parse::<Amount>(), Err(ParseAmountError::TooManyDecimals));
+        assert_eq!("1e5".parse::<Amount>(), Err(ParseAmountError::InvalidDigit));
+        assert_eq!("1.2.3".parse::<Amount>(), Err(ParseAmountError::InvalidDigit));
+        assert_eq!("99999999999999999999".parse::<Amount>(), Err(ParseAmountError::Overflow));
+    }
+
+    #[test]
+    fn test_addition_is_exact() {
+        let a: Amount = "0.1".parse().unwrap();
+        let b: Amount = "0.2".parse().unwrap();
+        assert_eq!(a.checked_add(b).unwrap().to_string(), "0.3000");
+    }
+
+    #[test]
+    fn test_checked_arithmetic_overflow() {
+        let max = Amount(i64::MAX);
+        assert_eq!(max.checked_add(Amount(1)), None);
+        assert_eq!(Amount(i64::MIN).checked_sub(Amount(1)), None);
+        assert_eq!(Amount(i64::MIN).checked_neg(), None);
+    }
+
+    #[test]
+    fn test_checked_percent_truncates() {
+        let amount: Amount = "10.0".parse().unwrap();
+        assert_eq!(amount.checked_percent("1.5".parse().unwrap()), Some(Amount(1_500)));
+        assert_eq!(Amount(3).checked_percent("50".parse().unwrap()), Some(Amount(1)));
+        assert_eq!(Amount(i64::MAX).checked_percent("200".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_display_four_places() {
+        assert_eq!(Amount::ZERO.to_string(), "0.0000");
+        assert_eq!(Amount(12_345).to_string(), "1.2345");
+        assert_eq!(Amount(-5).to_string(), "-0.0005");
+    }
+
+    #[test]
+    fn test_deserialize_from_csv() {
+        let mut rdr = csv::Reader::from_reader("amount\n1.25\n".as_bytes());
+        let amount: (Amount,) = rdr.deserialize().next().unwrap().unwrap();
+        assert_eq!(amount.0, Amount(12_500));
+    }
+
+    #[test]
+    fn test_serialize_as_string() {
+        assert_eq!(serde_json::to_string(&Amount(15_000)).unwrap(), "\"1.5000\"");
+        assert_eq!(serde_json::from_str::<Amount>("\"1.5000\"").unwrap(), Amount(15_000));
+    }
+
+    #[test]
+    fn test_deserialize_or_none() {
+        #[derive(Deserialize)]
+        struct Row {
+            #[serde(deserialize_with = "deserialize_or_none")]
+            amount: Option<Amount>,
+        }
+
+        let mut rdr = csv::Reader::from_reader("amount\n0.1\n7\n1.23456\n123456789012345.6789\n".as_bytes());
+        let rows: Vec<Row> = rdr.deserialize().map(|row| row.unwrap()).collect();
+        assert_eq!(rows[0].amount, Some(Amount(1_000)));
+        assert_eq!(rows[1].amount, Some(Amount(70_000)));
+        assert_eq!(rows[2].amount, None);
+        assert_eq!(rows[3].amount.unwrap().to_string(), "123456789012345.6789");
+    }
+}
@@ -0,0 +1,24 @@
+use std::env;
+use std::error::Error;
+use std::process;
+
+use oxidized_transactions::{serve, EngineConfig, ServiceOptions};
+
+const USAGE: &str = "usage: server [<address>] [--strict] [--resume-from <snapshot.json>] [<engine options>]";
+
+// listens on localhost unless told otherwise
+const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = ServiceOptions::parse(&args, DEFAULT_ADDRESS, true).unwrap_or_else(|err| {
+        eprintln!("{}\n{}\n{}", err, USAGE, EngineConfig::USAGE);
+        process::exit(2);
+    });
+
+    let engine = options.start_engine()?;
+    let listener = options.listen().await?;
+    serve(listener, engine, options.mode).await?;
+    Ok(())
+}
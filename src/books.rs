@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::{self, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::AccountInfo;
+use crate::amount::Amount;
+use crate::currency::Currency;
+use crate::ledger::{EventKind, LedgerEvent};
+
+// the two balances a client has on the books
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum SubAccount {
+    Available,
+    Held,
+}
+
+// an account on the books
+// client sub-accounts hold what is owed to the client, system accounts are where that money came from or went to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum BookAccount {
+    // deposits received
+    CashIn,
+    // withdrawals paid out, less withdrawals provisionally credited back while disputed
+    CashOut,
+    // deposits reversed by a chargeback
+    ChargebackLosses,
+    // balances the engine already held before it kept books, i.e. from a snapshot that had none
+    OpeningBalances,
+    // clearing account between the two sides of a transfer, both are posted together so it is back at zero after each one
+    Transfers,
+    // fees charged to clients, less the ones refunded
+    FeeIncome,
+    Client(u16, SubAccount),
+}
+
+impl fmt::Display for BookAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BookAccount::CashIn => f.write_str("cash_in"),
+            BookAccount::CashOut => f.write_str("cash_out"),
+            BookAccount::ChargebackLosses => f.write_str("chargeback_losses"),
+            BookAccount::OpeningBalances => f.write_str("opening_balances"),
+            BookAccount::Transfers => f.write_str("transfers"),
+            BookAccount::FeeIncome => f.write_str("fee_income"),
+            BookAccount::Client(client, SubAccount::Available) => write!(f, "client:{}:available", client),
+            BookAccount::Client(client, SubAccount::Held) => write!(f, "client:{}:held", client),
+        }
+    }
+}
+
+// one side of a double entry, a positive amount is a debit and a negative amount a credit
+// money owed to clients is a credit, so a deposit credits the client and debits cash_in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Posting {
+    pub account: BookAccount,
+    pub amount: Amount,
+}
+
+// the books do not hold together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BooksError {
+    // debits and credits differ by this much
+    Unbalanced(Amount),
+    // a client's sub-accounts disagree with its AccountInfo
+    ClientMismatch(u16),
+    // a balance or the sum of balances does not fit in an Amount
+    Overflow,
+}
+
+impl fmt::Display for BooksError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BooksError::Unbalanced(difference) => write!(f, "books are off by {}", difference),
+            BooksError::ClientMismatch(client) => write!(f, "books for client {} do not match its account", client),
+            BooksError::Overflow => write!(f, "books balance does not fit in an amount"),
+        }
+    }
+}
+
+impl std::error::Error for BooksError {}
+
+// the system account that takes the other side of an event
+// disputes and resolves of deposits and chargebacks of withdrawals only move money between available and held
+// their postings add up to zero on their own and nothing reaches the system account
+// a chargeback of a transfer hands the money back from the receiver to the sender, its two events cancel out on chargeback_losses
+fn counter_account(kind: EventKind) -> BookAccount {
+    match kind {
+        EventKind::Deposit => BookAccount::CashIn,
+        EventKind::Withdrawal | EventKind::Dispute | EventKind::Resolve => BookAccount::CashOut,
+        EventKind::Chargeback => BookAccount::ChargebackLosses,
+        EventKind::TransferOut | EventKind::TransferIn => BookAccount::Transfers,
+        EventKind::Fee | EventKind::FeeRefund => BookAccount::FeeIncome,
+        // freezes and unlocks never move money, nothing is ever booked for them
+        EventKind::Freeze | EventKind::Unlock => BookAccount::ChargebackLosses,
+    }
+}
+
+// the balanced postings for a ledger event, changes to available and held are credited to the client
+// and whatever they add up to is booked against the event's system account
+pub fn postings(event: &LedgerEvent) -> Option<Vec<Posting>> {
+    let mut postings = vec![];
+    let mut remainder = Amount::ZERO;
+    for (sub_account, delta) in [(SubAccount::Available, event.delta_available), (SubAccount::Held, event.delta_held)] {
+        if delta != Amount::ZERO {
+            postings.push(Posting { account: BookAccount::Client(event.client, sub_account), amount: delta.checked_neg()? });
+            remainder = remainder.checked_add(delta)?;
+        }
+    }
+
+    if remainder != Amount::ZERO {
+        postings.push(Posting { account: counter_account(event.kind), amount: remainder });
+    }
+    Some(postings)
+}
+
+// running balance of every book account, kept apart per currency
+// None is the default currency of transactions without one, money never moves between currencies
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Books {
+    pub(crate) balances: BTreeMap<(Option<Currency>, BookAccount), Amount>,
+}
+
+impl Books {
+    // books for accounts that were never posted, every client balance is set against opening_balances
+    // only default currency accounts can predate the books
+    pub(crate) fn opening(accounts: &HashMap<u16, AccountInfo>) -> Option<Books> {
+        let mut books = Books::default();
+        for (client, account_info) in accounts {
+            let postings = [
+                Posting { account: BookAccount::Client(*client, SubAccount::Available), amount: account_info.available.checked_neg()? },
+                Posting { account: BookAccount::Client(*client, SubAccount::Held), amount: account_info.held.checked_neg()? },
+                Posting { account: BookAccount::OpeningBalances, amount: account_info.total },
+            ];
+            books.post(None, &postings)?;
+        }
+        Some(books)
+    }
+
+    // posts every entry or none, None when a balance would overflow
+    // the postings of one entry are all in the same currency
+    pub(crate) fn post(&mut self, currency: Option<Currency>, postings: &[Posting]) -> Option<()> {
+        let mut updated = vec![];
+        for posting in postings {
+            let balance = updated
+                .iter()
+                .rev()
+                .find(|((_, account), _)| *account == posting.account)
+                .map(|(_, balance)| *balance)
+                .unwrap_or_else(|| self.balance_in(currency, posting.account));
+            updated.push(((currency, posting.account), balance.checked_add(posting.amount)?));
+        }
+
+        self.balances.extend(updated);
+        Some(())
+    }
+
+    // balance in the default currency
+    pub fn balance(&self, account: BookAccount) -> Amount {
+        self.balance_in(None, account)
+    }
+
+    pub fn balance_in(&self, currency: Option<Currency>, account: BookAccount) -> Amount {
+        self.balances.get(&(currency, account)).copied().unwrap_or(Amount::ZERO)
+    }
+
+    // every account with its balance, the default currency first then by currency code
+    // within a currency system accounts come first then clients by id
+    pub fn trial_balance(&self) -> impl Iterator<Item = (Option<Currency>, BookAccount, Amount)> + '_ {
+        self.balances.iter().map(|((currency, account), balance)| (*currency, *account, *balance))
+    }
+
+    // the books of every currency sum to zero, and each client's sub-accounts are exactly the available and held
+    // of its balance in that currency
+    pub(crate) fn check<'a, I>(&self, balances: I) -> Result<(), BooksError>
+    where
+        I: IntoIterator<Item = (u16, Option<Currency>, &'a AccountInfo)>,
+    {
+        let mut sums: BTreeMap<Option<Currency>, Amount> = BTreeMap::new();
+        for ((currency, _), balance) in &self.balances {
+            let sum = sums.entry(*currency).or_insert(Amount::ZERO);
+            *sum = sum.checked_add(*balance).ok_or(BooksError::Overflow)?;
+        }
+        if let Some(sum) = sums.into_values().find(|sum| *sum != Amount::ZERO) {
+            return Err(BooksError::Unbalanced(sum));
+        }
+
+        for (client, currency, account_info) in balances {
+            let available = self.balance_in(currency, BookAccount::Client(client, SubAccount::Available)).checked_neg();
+            let held = self.balance_in(currency, BookAccount::Client(client, SubAccount::Held)).checked_neg();
+            if available != Some(account_info.available) || held != Some(account_info.held) {
+                return Err(BooksError::ClientMismatch(client));
+            }
+        }
+        Ok(())
+    }
+}
+
+// writes the trial balance as csv, one row per book account with its balance in the debit or credit column
+// each currency ends with a row holding its column totals, which are equal when its books balance
+// accounts in a currency other than the default are prefixed with its code, e.g. EUR:cash_in and EUR:total
+pub fn write_trial_balance<W: Write>(output: W, books: &Books) -> io::Result<()> {
+    let mut wtr = csv::Writer::from_writer(output);
+    wtr.write_record(["account", "debit", "credit"])?;
+
+    let name = |currency: Option<Currency>, account: &dyn fmt::Display| match currency {
+        Some(currency) => format!("{}:{}", currency, account),
+        None => account.to_string(),
+    };
+    let overflow = || io::Error::new(io::ErrorKind::InvalidData, BooksError::Overflow);
+
+    // the default currency always gets its total row, even without any accounts
+    let mut current = None;
+    let mut debits = Amount::ZERO;
+    let mut credits = Amount::ZERO;
+    for (currency, account, balance) in books.trial_balance() {
+        if currency != current {
+            wtr.write_record([name(current, &"total"), debits.to_string(), credits.to_string()])?;
+            (current, debits, credits) = (currency, Amount::ZERO, Amount::ZERO);
+        }
+
+        let (debit, credit) = if balance.is_positive() { (balance, Amount::ZERO) } else { (Amount::ZERO, balance.checked_neg().ok_or_else(overflow)?) };
+        debits = debits.checked_add(debit).ok_or_else(overflow)?;
+        credits = credits.checked_add(credit).ok_or_else(overflow)?;
+        wtr.write_record([name(currency, &account), debit.to_string(), credit.to_string()])?;
+    }
+
+    wtr.write_record([name(current, &"total"), debits.to_string(), credits.to_string()])?;
+    wtr.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::test_support::transaction;
+    use crate::transaction::Transaction;
+
+    fn to_amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    // every dispute path for both transaction kinds
+    fn engine_with_history() -> Engine {
+        let mut engine = Engine::new();
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 2, "3.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("resolve", 1, 2, "")).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "")).unwrap();
+        engine.apply(transaction("resolve", 1, 1, "")).unwrap();
+        engine.apply(transaction("deposit", 2, 3, "5.0")).unwrap();
+        engine.apply(transaction("dispute", 2, 3, "")).unwrap();
+        engine.apply(transaction("chargeback", 2, 3, "")).unwrap();
+        engine.apply(transaction("deposit", 3, 4, "4.0")).unwrap();
+        engine.apply(transaction("withdrawal", 3, 5, "1.5")).unwrap();
+        engine.apply(transaction("dispute", 3, 5, "")).unwrap();
+        engine.apply(transaction("chargeback", 3, 5, "")).unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_postings_balance_for_every_event() {
+        let engine = engine_with_history();
+
+        for event in engine.ledger() {
+            let sum = postings(event).unwrap().iter().try_fold(Amount::ZERO, |sum, posting| sum.checked_add(posting.amount));
+            assert_eq!(sum, Some(Amount::ZERO), "event {}", event.seq);
+        }
+    }
+
+    #[test]
+    fn test_deposit_and_withdrawal_postings() {
+        let engine = engine_with_history();
+
+        assert_eq!(
+            postings(&engine.ledger()[0]).unwrap(),
+            vec![
+                Posting { account: BookAccount::Client(1, SubAccount::Available), amount: to_amount("-10.0") },
+                Posting { account: BookAccount::CashIn, amount: to_amount("10.0") },
+            ]
+        );
+        assert_eq!(
+            postings(&engine.ledger()[1]).unwrap(),
+            vec![
+                Posting { account: BookAccount::Client(1, SubAccount::Available), amount: to_amount("3.0") },
+                Posting { account: BookAccount::CashOut, amount: to_amount("-3.0") },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_books_check_out_after_every_dispute_path() {
+        let engine = engine_with_history();
+
+        assert_eq!(engine.check_books(), Ok(()));
+        assert_eq!(engine.books().balance(BookAccount::CashIn), to_amount("19.0"));
+        // client 3's withdrawal was charged back, so only client 1's withdrawal was paid out
+        assert_eq!(engine.books().balance(BookAccount::CashOut), to_amount("-3.0"));
+        assert_eq!(engine.books().balance(BookAccount::ChargebackLosses), to_amount("-5.0"));
+    }
+
+    #[test]
+    fn test_books_check_catches_drift() {
+        let mut engine = engine_with_history();
+        engine.accounts.get_mut(&1).unwrap().available = to_amount("1.0");
+        assert_eq!(engine.check_books(), Err(BooksError::ClientMismatch(1)));
+
+        let mut books = engine.books().clone();
+        books.post(None, &[Posting { account: BookAccount::CashIn, amount: to_amount("1.0") }]).unwrap();
+        assert_eq!(books.check(engine.balances()), Err(BooksError::Unbalanced(to_amount("1.0"))));
+    }
+
+    #[test]
+    fn test_books_opening_balances_for_snapshot_without_books() {
+        let engine = engine_with_history();
+        let books = Books::opening(&engine.accounts).unwrap();
+
+        assert_eq!(books.check(engine.balances()), Ok(()));
+        assert_eq!(books.balance(BookAccount::OpeningBalances), to_amount("11.0"));
+    }
+
+    #[test]
+    fn test_write_trial_balance() {
+        let mut engine = Engine::new();
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 2, "3.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "")).unwrap();
+
+        let mut output = vec![];
+        write_trial_balance(&mut output, engine.books()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "account,debit,credit\n\
+             cash_in,10.0000,0.0000\n\
+             cash_out,0.0000,3.0000\n\
+             client:1:available,3.0000,0.0000\n\
+             client:1:held,0.0000,10.0000\n\
+             total,13.0000,13.0000\n"
+        );
+    }
+
+    #[test]
+    fn test_write_trial_balance_per_currency() {
+        let mut engine = Engine::new();
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(Transaction { currency: "eur".parse().ok(), ..transaction("deposit", 2, 2, "4.0") }).unwrap();
+        engine.apply(Transaction { currency: "eur".parse().ok(), ..transaction("withdrawal", 2, 3, "1.0") }).unwrap();
+
+        assert_eq!(engine.check_books(), Ok(()));
+        let mut output = vec![];
+        write_trial_balance(&mut output, engine.books()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "account,debit,credit\n\
+             cash_in,10.0000,0.0000\n\
+             client:1:available,0.0000,10.0000\n\
+             total,10.0000,10.0000\n\
+             EUR:cash_in,4.0000,0.0000\n\
+             EUR:cash_out,0.0000,1.0000\n\
+             EUR:client:2:available,0.0000,3.0000\n\
+             EUR:total,4.0000,4.0000\n"
+        );
+    }
+}
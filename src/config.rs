@@ -0,0 +1,337 @@
+use std::str::FromStr;
+
+use crate::amount::Amount;
+
+// what a dispute on a withdrawal does
+// disputes on deposits always move the deposited amount from available to held
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WithdrawalDisputes {
+    // the withdrawn amount is provisionally credited to held, so total goes up but nothing becomes spendable
+    // resolve: the withdrawal stands, the credit is taken back out of held
+    // chargeback: the withdrawal is reversed, the credit moves from held to available and the account is locked
+    #[default]
+    ProvisionalCredit,
+    // disputes on withdrawals are rejected, only deposits can be disputed
+    Reject,
+}
+
+impl FromStr for WithdrawalDisputes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "credit" => Ok(WithdrawalDisputes::ProvisionalCredit),
+            "reject" => Ok(WithdrawalDisputes::Reject),
+            _ => Err(format!("unknown withdrawal dispute policy '{}', expected credit or reject", s)),
+        }
+    }
+}
+
+// how much is charged on top of a transaction, always in the currency of the transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fee {
+    // the same fee whatever the amount, written as "0.5"
+    Flat(Amount),
+    // percent of the amount cut off after four decimal places, written as "1.5%"
+    Percent(Amount),
+    // (from, fee) sorted by from, the last tier whose from is at most the amount applies
+    // an amount below the first tier is free, written as "0:1.0,100:0.5%"
+    Tiered(Vec<(Amount, Fee)>),
+}
+
+impl Fee {
+    // the fee on an amount, None if it does not fit in an Amount
+    pub fn charge(&self, amount: Amount) -> Option<Amount> {
+        match self {
+            Fee::Flat(fee) => Some(*fee),
+            Fee::Percent(percent) => amount.checked_percent(*percent),
+            Fee::Tiered(tiers) => match tiers.iter().rev().find(|(from, _)| *from <= amount) {
+                Some((_, fee)) => fee.charge(amount),
+                None => Some(Amount::ZERO),
+            },
+        }
+    }
+}
+
+impl FromStr for Fee {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(':') {
+            let mut tiers = vec![];
+            for tier in s.split(',') {
+                let (from, fee) = tier.split_once(':').ok_or_else(|| format!("fee tier '{}' is not from:fee", tier))?;
+                let from = from.parse::<Amount>().map_err(|err| format!("fee tier '{}': {}", tier, err))?;
+                // a tier is a flat or percentage fee, tiers do not nest
+                let fee = if fee.contains(':') { Err(format!("fee tier '{}' is not from:fee", tier)) } else { fee.parse() }?;
+                tiers.push((from, fee));
+            }
+            tiers.sort_by_key(|(from, _)| *from);
+            return Ok(Fee::Tiered(tiers));
+        }
+
+        let (number, percent) = match s.strip_suffix('%') {
+            Some(number) => (number, true),
+            None => (s, false),
+        };
+        let amount = number.parse::<Amount>().map_err(|err| format!("fee '{}': {}", s, err))?;
+        if amount < Amount::ZERO {
+            return Err(format!("fee '{}' is negative", s));
+        }
+        Ok(if percent { Fee::Percent(amount) } else { Fee::Flat(amount) })
+    }
+}
+
+// the fee charged per transaction type, only withdrawals and chargebacks have fees
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FeeSchedule {
+    pub withdrawal: Option<Fee>,
+    pub chargeback: Option<Fee>,
+}
+
+impl FeeSchedule {
+    // sets the fee of one transaction type from a "type=fee" argument, e.g. "withdrawal=1.5%"
+    pub fn set(&mut self, arg: &str) -> Result<(), String> {
+        let (trans_type, fee) = arg.split_once('=').ok_or_else(|| format!("fee '{}' is not type=fee", arg))?;
+        let fee = Some(fee.parse()?);
+        match &*trans_type.to_lowercase() {
+            "withdrawal" => self.withdrawal = fee,
+            "chargeback" => self.chargeback = fee,
+            _ => return Err(format!("no fees on '{}', expected withdrawal or chargeback", trans_type)),
+        }
+        Ok(())
+    }
+
+    // the fee on a transaction of a type, expects the type to be lowercase already
+    // zero for types without a fee, None if it does not fit in an Amount
+    pub fn charge(&self, trans_type: &str, amount: Amount) -> Option<Amount> {
+        let fee = match trans_type {
+            "withdrawal" => &self.withdrawal,
+            "chargeback" => &self.chargeback,
+            _ => &None,
+        };
+        fee.as_ref().map_or(Some(Amount::ZERO), |fee| fee.charge(amount))
+    }
+}
+
+// which transaction types a locked client still accepts, everything else is rejected as account_locked
+// the admin freeze and unlock rows are not part of it, they always reach a locked client
+// a transfer is checked against both clients, transfer for a locked sender and deposit for a locked receiver,
+// and disputes, resolves and chargebacks of a transfer against both of them for their own type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockPolicy {
+    pub deposit: bool,
+    pub withdrawal: bool,
+    pub transfer: bool,
+    pub dispute: bool,
+    pub resolve: bool,
+    pub chargeback: bool,
+}
+
+// the default lets the disputes that were open when the client was locked settle and nothing else
+impl Default for LockPolicy {
+    fn default() -> LockPolicy {
+        LockPolicy { resolve: true, chargeback: true, ..LockPolicy::NONE }
+    }
+}
+
+impl LockPolicy {
+    pub const NONE: LockPolicy = LockPolicy { deposit: false, withdrawal: false, transfer: false, dispute: false, resolve: false, chargeback: false };
+
+    // whether a locked client accepts a transaction of the type, expects the type to be lowercase already
+    pub fn accepts(&self, trans_type: &str) -> bool {
+        match trans_type {
+            "deposit" => self.deposit,
+            "withdrawal" => self.withdrawal,
+            "transfer" => self.transfer,
+            "dispute" => self.dispute,
+            "resolve" => self.resolve,
+            "chargeback" => self.chargeback,
+            _ => false,
+        }
+    }
+}
+
+// a comma separated list of the accepted types, e.g. "deposit,resolve,chargeback", or "none"
+impl FromStr for LockPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = LockPolicy::NONE;
+        if s == "none" {
+            return Ok(policy);
+        }
+
+        for trans_type in s.split(',') {
+            let accepted = match trans_type.trim() {
+                "deposit" => &mut policy.deposit,
+                "withdrawal" => &mut policy.withdrawal,
+                "transfer" => &mut policy.transfer,
+                "dispute" => &mut policy.dispute,
+                "resolve" => &mut policy.resolve,
+                "chargeback" => &mut policy.chargeback,
+                _ => return Err(format!("unknown transaction type '{}' for locked accounts, expected none or a list of deposit, withdrawal, transfer, dispute, resolve, chargeback", trans_type)),
+            };
+            *accepted = true;
+        }
+        Ok(policy)
+    }
+}
+
+// what happens to a dispute that is still open when its resolution deadline passes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeadlineAction {
+    // the dispute is resolved, as if a resolve row had come in
+    #[default]
+    Resolve,
+    // the dispute is charged back, as if a chargeback row had come in
+    Chargeback,
+}
+
+impl FromStr for DeadlineAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "resolve" => Ok(DeadlineAction::Resolve),
+            "chargeback" => Ok(DeadlineAction::Chargeback),
+            _ => Err(format!("unknown deadline action '{}', expected resolve or chargeback", s)),
+        }
+    }
+}
+
+// a number of seconds from "90", "90s", "45m", "36h" or "120d"
+pub fn parse_duration(s: &str) -> Result<u64, String> {
+    let (number, scale) = match s.strip_suffix('d') {
+        Some(number) => (number, 86_400),
+        None => match s.strip_suffix('h') {
+            Some(number) => (number, 3_600),
+            None => match s.strip_suffix('m') {
+                Some(number) => (number, 60),
+                None => (s.strip_suffix('s').unwrap_or(s), 1),
+            },
+        },
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .ok_or_else(|| format!("invalid duration '{}', expected a number of seconds or e.g. 45m, 36h, 120d", s))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct EngineConfig {
+    pub withdrawal_disputes: WithdrawalDisputes,
+    pub fees: FeeSchedule,
+    pub locked_accepts: LockPolicy,
+    // seconds after a transaction's timestamp it can still be disputed, None for no limit
+    pub dispute_window: Option<u64>,
+    // seconds an open dispute has to be resolved or charged back in, None for no deadline
+    pub resolve_within: Option<u64>,
+    // what the engine does itself with a dispute whose deadline the clock has passed
+    pub on_deadline: DeadlineAction,
+}
+
+impl EngineConfig {
+    // the options parse_flag knows, every binary prints them after its own usage
+    pub const USAGE: &'static str = "engine options: [--withdrawal-disputes credit|reject] [--fee withdrawal|chargeback=<fee>]... [--locked-accepts <types>|none]
+       [--dispute-window <duration>] [--resolve-within <duration>] [--on-deadline resolve|chargeback]";
+
+    // sets the option flag names from the value that follows it in args, so every binary takes the same options
+    // Ok(false) if flag is not an engine option, nothing is taken from args then
+    pub fn parse_flag<S: AsRef<str>>(&mut self, flag: &str, args: &mut impl Iterator<Item = S>) -> Result<bool, String> {
+        let mut value = |missing: &str| args.next().map(|value| value.as_ref().to_string()).ok_or_else(|| missing.to_string());
+        match flag {
+            "--withdrawal-disputes" => self.withdrawal_disputes = value("--withdrawal-disputes needs credit or reject")?.parse()?,
+            "--fee" => self.fees.set(&value("--fee needs type=fee, e.g. withdrawal=1.5%")?)?,
+            "--locked-accepts" => {
+                self.locked_accepts = value("--locked-accepts needs none or a list of transaction types, e.g. resolve,chargeback")?.parse()?
+            }
+            "--dispute-window" => self.dispute_window = Some(parse_duration(&value("--dispute-window needs a duration, e.g. 120d")?)?),
+            "--resolve-within" => self.resolve_within = Some(parse_duration(&value("--resolve-within needs a duration, e.g. 30d")?)?),
+            "--on-deadline" => self.on_deadline = value("--on-deadline needs resolve or chargeback")?.parse()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_fee_parse_and_charge() {
+        let amount = to_amount("200.0");
+        assert_eq!("0.5".parse::<Fee>().unwrap().charge(amount), Some(to_amount("0.5")));
+        assert_eq!("1.5%".parse::<Fee>().unwrap().charge(amount), Some(to_amount("3.0")));
+
+        // tiers can be given in any order
+        let tiered: Fee = "100:0.5%,10:1.0".parse().unwrap();
+        assert_eq!(tiered.charge(to_amount("5.0")), Some(Amount::ZERO));
+        assert_eq!(tiered.charge(to_amount("10.0")), Some(to_amount("1.0")));
+        assert_eq!(tiered.charge(amount), Some(to_amount("1.0")));
+        assert_eq!(tiered.charge(to_amount("400.0")), Some(to_amount("2.0")));
+
+        assert!("-1".parse::<Fee>().is_err());
+        assert!("1.5%%".parse::<Fee>().is_err());
+        assert!("0:1,ten:2".parse::<Fee>().is_err());
+    }
+
+    #[test]
+    fn test_lock_policy_parse() {
+        let policy: LockPolicy = "deposit, resolve".parse().unwrap();
+        assert!(policy.accepts("deposit") && policy.accepts("resolve"));
+        assert!(!policy.accepts("chargeback") && !policy.accepts("withdrawal"));
+        assert_eq!("none".parse::<LockPolicy>(), Ok(LockPolicy::NONE));
+        assert_eq!("resolve,chargeback".parse::<LockPolicy>(), Ok(LockPolicy::default()));
+        assert!("deposit,freeze".parse::<LockPolicy>().is_err());
+        assert!("".parse::<LockPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_parse_duration_units() {
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("45m"), Ok(2_700));
+        assert_eq!(parse_duration("36h"), Ok(129_600));
+        assert_eq!(parse_duration("120d"), Ok(10_368_000));
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("-1d").is_err());
+        assert!(parse_duration("2w").is_err());
+    }
+
+    #[test]
+    fn test_parse_flag_takes_engine_options() {
+        let mut config = EngineConfig::default();
+        let mut args = ["reject", "withdrawal=1%", "2d", "out.json"].into_iter();
+        assert_eq!(config.parse_flag("--withdrawal-disputes", &mut args), Ok(true));
+        assert_eq!(config.parse_flag("--fee", &mut args), Ok(true));
+        assert_eq!(config.parse_flag("--resolve-within", &mut args), Ok(true));
+        assert_eq!(config.parse_flag("--snapshot-to", &mut args), Ok(false));
+        assert_eq!(args.next(), Some("out.json"));
+
+        assert_eq!(config.withdrawal_disputes, WithdrawalDisputes::Reject);
+        assert_eq!(config.fees.withdrawal, Some(Fee::Percent(to_amount("1.0"))));
+        assert_eq!(config.resolve_within, Some(172_800));
+        assert!(config.parse_flag("--on-deadline", &mut args).is_err());
+        assert!(config.parse_flag("--locked-accepts", &mut ["everything"].into_iter()).is_err());
+    }
+
+    #[test]
+    fn test_fee_schedule_per_type() {
+        let mut fees = FeeSchedule::default();
+        fees.set("Withdrawal=1%").unwrap();
+        fees.set("chargeback=15").unwrap();
+
+        assert_eq!(fees.charge("withdrawal", to_amount("50.0")), Some(to_amount("0.5")));
+        assert_eq!(fees.charge("chargeback", to_amount("50.0")), Some(to_amount("15.0")));
+        assert_eq!(fees.charge("deposit", to_amount("50.0")), Some(Amount::ZERO));
+        assert!(fees.set("deposit=1").is_err());
+        assert!(fees.set("withdrawal").is_err());
+    }
+}
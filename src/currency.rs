@@ -0,0 +1,101 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// an ISO 4217 style currency code, three ascii letters
+// codes are case-insensitive on input and always kept in upper case, so "eur" and "EUR" are the same currency
+// a Copy code rather than a String keeps it cheap to carry on every transaction, event and book account
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency([u8; 3]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseCurrencyError;
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        // only ever built from ascii letters
+        std::str::from_utf8(&self.0).unwrap()
+    }
+}
+
+impl FromStr for Currency {
+    type Err = ParseCurrencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code: [u8; 3] = s.trim().as_bytes().try_into().map_err(|_| ParseCurrencyError)?;
+        if !code.iter().all(u8::is_ascii_alphabetic) {
+            return Err(ParseCurrencyError);
+        }
+        Ok(Currency(code.map(|b| b.to_ascii_uppercase())))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// reader.rs tells currency errors from amount errors by this message
+impl fmt::Display for ParseCurrencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid currency, expected a three letter code")
+    }
+}
+
+impl std::error::Error for ParseCurrencyError {}
+
+struct CurrencyVisitor;
+
+impl<'de> Visitor<'de> for CurrencyVisitor {
+    type Value = Currency;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a three letter currency code")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Currency, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(CurrencyVisitor)
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_normalizes_case() {
+        assert_eq!("eur".parse::<Currency>().unwrap().to_string(), "EUR");
+        assert_eq!(" Usd ".parse::<Currency>().unwrap(), "USD".parse().unwrap());
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid() {
+        assert_eq!("".parse::<Currency>(), Err(ParseCurrencyError));
+        assert_eq!("EURO".parse::<Currency>(), Err(ParseCurrencyError));
+        assert_eq!("E1R".parse::<Currency>(), Err(ParseCurrencyError));
+        assert_eq!("€".parse::<Currency>(), Err(ParseCurrencyError));
+    }
+
+    #[test]
+    fn test_serde_as_string() {
+        let currency: Currency = "gbp".parse().unwrap();
+        assert_eq!(serde_json::to_string(&currency).unwrap(), "\"GBP\"");
+        assert_eq!(serde_json::from_str::<Currency>("\"gbp\"").unwrap(), currency);
+        assert!(serde_json::from_str::<Currency>("\"pounds\"").is_err());
+    }
+}
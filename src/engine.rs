@@ -0,0 +1,2972 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use crate::account::AccountInfo;
+use crate::amount::Amount;
+use crate::books::{postings, Books, BooksError};
+use crate::config::{DeadlineAction, EngineConfig, WithdrawalDisputes};
+use crate::currency::Currency;
+use crate::ledger::{EventKind, LedgerEvent};
+use crate::transaction::{Dispute, Transaction, TransactionStatus};
+
+// what an applied transaction did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Deposited,
+    Withdrew,
+    Disputed,
+    Resolved,
+    ChargedBack,
+    Transferred,
+    Frozen,
+    Unlocked,
+}
+
+impl Outcome {
+    // stable snake_case name, used in api responses
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Deposited => "deposited",
+            Outcome::Withdrew => "withdrew",
+            Outcome::Disputed => "disputed",
+            Outcome::Resolved => "resolved",
+            Outcome::ChargedBack => "charged_back",
+            Outcome::Transferred => "transferred",
+            Outcome::Frozen => "frozen",
+            Outcome::Unlocked => "unlocked",
+        }
+    }
+}
+
+// why a transaction did nothing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    // type is not one of deposit, withdrawal, transfer, dispute, resolve, chargeback, freeze, unlock
+    UnknownType,
+    // deposits, withdrawals and transfers need a positive amount
+    InvalidAmount,
+    // transfer without a destination or to the sending client itself
+    InvalidDestination,
+    AccountLocked,
+    // unlock of a client that is not locked
+    NotLocked,
+    // freeze or unlock without a reason
+    MissingReason,
+    // withdrawal, transfer, freeze or unlock of a client that has never deposited
+    UnknownAccount,
+    InsufficientFunds,
+    // dispute, resolve or chargeback for a tx the client does not have
+    UnknownTransaction,
+    // deposit, withdrawal or transfer reusing a tx id that has already been seen, for any client
+    DuplicateTransaction,
+    // dispute, resolve or chargeback for a tx that belongs to another client
+    ClientMismatch,
+    AlreadyDisputed,
+    NotDisputed,
+    // disputes on withdrawals are turned off, or the tx was already charged back
+    NotDisputable,
+    // dispute of a tx older than the dispute window
+    DisputeWindowClosed,
+    // dispute of more than the part of the tx that is neither disputed nor charged back yet
+    ExceedsUndisputed,
+    // dispute, resolve or chargeback naming another currency than its tx was in
+    CurrencyMismatch,
+    // the balance would not fit in an Amount
+    Overflow,
+}
+
+impl RejectReason {
+    // stable snake_case name, used in reports
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::UnknownType => "unknown_type",
+            RejectReason::InvalidAmount => "invalid_amount",
+            RejectReason::InvalidDestination => "invalid_destination",
+            RejectReason::AccountLocked => "account_locked",
+            RejectReason::NotLocked => "not_locked",
+            RejectReason::MissingReason => "missing_reason",
+            RejectReason::UnknownAccount => "unknown_account",
+            RejectReason::InsufficientFunds => "insufficient_funds",
+            RejectReason::UnknownTransaction => "unknown_transaction",
+            RejectReason::DuplicateTransaction => "duplicate_transaction",
+            RejectReason::ClientMismatch => "client_mismatch",
+            RejectReason::AlreadyDisputed => "already_disputed",
+            RejectReason::NotDisputed => "not_disputed",
+            RejectReason::NotDisputable => "not_disputable",
+            RejectReason::DisputeWindowClosed => "dispute_window_closed",
+            RejectReason::ExceedsUndisputed => "exceeds_undisputed",
+            RejectReason::CurrencyMismatch => "currency_mismatch",
+            RejectReason::Overflow => "overflow",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// why a transaction was not applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxError {
+    // the transaction was invalid or not allowed for the account, no state was changed
+    Rejected(RejectReason),
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::Rejected(reason) => write!(f, "transaction rejected: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for TxError {}
+
+impl From<RejectReason> for TxError {
+    fn from(reason: RejectReason) -> TxError {
+        TxError::Rejected(reason)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Engine {
+    pub(crate) config: EngineConfig,
+
+    // map to track account data, balances in the default currency
+    // key: client
+    // value: AccountInfo struct
+    pub(crate) accounts: HashMap<u16, AccountInfo>,
+
+    // balances in named currencies, a client only has the ones it has deposited in
+    // locked is the client's, it is the same in every currency and in accounts
+    // key: client
+    // value: currency -> AccountInfo struct
+    pub(crate) currency_accounts: HashMap<u16, HashMap<Currency, AccountInfo>>,
+
+    // tracks transactions for dispute/resolve/chargeback
+    // kept when the client is locked, so an unlocked client can still dispute its earlier transactions
+    // key: client
+    // value: tx -> TransactionStatus struct
+    pub(crate) transaction_status: HashMap<u16, HashMap<u32, TransactionStatus>>,
+
+    // global tx id index, tx ids are unique across all clients
+    // every deposit/withdrawal/transfer/freeze/unlock claims its tx id, even when it is then rejected, so a replay can never apply
+    // kept for locked clients too
+    // key: tx
+    // value: client
+    pub(crate) tx_clients: HashMap<u32, u16>,
+
+    // sequence number of the last journal entry applied, see journal.rs
+    pub(crate) journal_seq: u64,
+
+    // balance movements not yet taken by the caller, see ledger.rs
+    pub(crate) ledger: Vec<LedgerEvent>,
+
+    // sequence number of the last ledger event emitted
+    pub(crate) ledger_seq: u64,
+
+    // double-entry books, every ledger event is posted here, see books.rs
+    pub(crate) books: Books,
+
+    // the latest timestamp of any row applied, None until a row has one
+    pub(crate) clock: Option<u64>,
+
+    // open disputes with a resolution deadline, the earliest first
+    // value: (deadline, tx)
+    pub(crate) deadlines: BTreeSet<(u64, u32)>,
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine::default()
+    }
+
+    pub fn with_config(config: EngineConfig) -> Engine {
+        Engine { config, ..Engine::default() }
+    }
+
+    pub fn apply(&mut self, transaction: Transaction) -> Result<Outcome, TxError> {
+        Ok(self.handle_record(transaction)?)
+    }
+
+    // balance in the default currency
+    pub fn account(&self, client: u16) -> Option<&AccountInfo> {
+        self.accounts.get(&client)
+    }
+
+    pub fn account_in(&self, client: u16, currency: Currency) -> Option<&AccountInfo> {
+        self.currency_accounts.get(&client).and_then(|balances| balances.get(&currency))
+    }
+
+    // iterates default currency accounts in no particular order
+    pub fn accounts(&self) -> impl Iterator<Item = (u16, &AccountInfo)> {
+        self.accounts.iter().map(|(client, account_info)| (*client, account_info))
+    }
+
+    // iterates the balance of every client in every currency it has, None being the default currency, in no particular order
+    pub fn balances(&self) -> impl Iterator<Item = (u16, Option<Currency>, &AccountInfo)> {
+        let defaults = self.accounts.iter().map(|(client, account_info)| (*client, None, account_info));
+        let named = self.currency_accounts.iter().flat_map(|(client, balances)| {
+            balances.iter().map(|(currency, account_info)| (*client, Some(*currency), account_info))
+        });
+        defaults.chain(named)
+    }
+
+    pub fn transaction(&self, client: u16, trans_id: u32) -> Option<&TransactionStatus> {
+        self.transaction_status.get(&client).and_then(|statuses| statuses.get(&trans_id))
+    }
+
+    // looks a transaction up by tx id alone, tx ids are unique across clients
+    // None for ids that did not leave a transaction behind, e.g. rejected rows and freezes
+    pub fn find_transaction(&self, trans_id: u32) -> Option<(u16, &TransactionStatus)> {
+        let client = *self.tx_clients.get(&trans_id)?;
+        self.transaction(client, trans_id).map(|status| (client, status))
+    }
+
+    // ledger events emitted since they were last taken, oldest first
+    pub fn ledger(&self) -> &[LedgerEvent] {
+        &self.ledger
+    }
+
+    pub fn books(&self) -> &Books {
+        &self.books
+    }
+
+    // the invariant checker, the books sum to zero and agree with every account
+    pub fn check_books(&self) -> Result<(), BooksError> {
+        self.books.check(self.balances())
+    }
+
+    // hands the ledger events over to the caller, e.g. to append them to a file
+    // the engine keeps every event until it is taken
+    pub fn take_ledger(&mut self) -> Vec<LedgerEvent> {
+        std::mem::take(&mut self.ledger)
+    }
+
+    // the engine's clock, the latest timestamp it has seen
+    pub fn clock(&self) -> Option<u64> {
+        self.clock
+    }
+
+    // moves the clock forward to now, it never goes back
+    // open disputes whose deadline has passed are settled as the config says, their ledger events carry the reason
+    // rows with a timestamp move the clock themselves, this is for embedders that keep time another way, e.g. a timer
+    pub fn advance_clock(&mut self, now: u64) {
+        self.clock = Some(self.clock.map_or(now, |clock| clock.max(now)));
+        self.settle_expired_disputes();
+    }
+
+    fn handle_record(&mut self, transaction: Transaction) -> Result<Outcome, RejectReason> {
+        let mut transaction = transaction;
+        transaction.trans_type = transaction.trans_type.to_lowercase();
+
+        // only a row that passes the checks moves the clock, a rejected one cannot settle disputes whose deadline it is past
+        // the row is checked again once it is applied, settling may have changed what it finds, e.g. locked its client
+        let owner = index_trans_id(&mut self.tx_clients, &transaction)?;
+        self.validate(&transaction, owner)?;
+        if let Some(timestamp) = transaction.timestamp {
+            self.advance_clock(timestamp);
+        }
+        self.handle_indexed(transaction, owner)
+    }
+
+    pub(crate) fn handle_indexed(&mut self, transaction: Transaction, owner: Option<u16>) -> Result<Outcome, RejectReason> {
+        // handle_indexed applies a transaction that has already been through the tx id index
+        // the type is already lowercase and owner is the client the tx id belongs to, if it is known
+        self.validate(&transaction, owner)?;
+        let amount = transaction.amount;
+        let client = transaction.client;
+        let trans_id = transaction.id;
+        let currency = transaction.currency;
+
+        // amount is optional for dispute, resolve, chargeback, a dispute without one holds what is left undisputed of the tx
+        // a resolve or chargeback without one settles the oldest open dispute of the tx, with one the oldest open dispute of that amount
+        // a resolve or chargeback naming a dispute id settles that dispute, an amount given with it has to be the dispute's
+        // a zero amount counts as none, older files put it there
+        let part = amount.filter(|amount| *amount != Amount::ZERO);
+
+        match &*transaction.trans_type {
+            "freeze" => {
+                self.handle_freeze(&client, trans_id, transaction.reason)?;
+                Ok(Outcome::Frozen)
+            }
+            "unlock" => {
+                self.handle_unlock(&client, trans_id, transaction.reason)?;
+                Ok(Outcome::Unlocked)
+            }
+            "deposit" => {
+                let amount = amount.ok_or(RejectReason::InvalidAmount)?;
+                self.handle_deposit(amount, currency, &client, trans_id)?;
+                self.set_timestamp(&client, &trans_id, transaction.timestamp);
+                Ok(Outcome::Deposited)
+            }
+            "withdrawal" => {
+                let amount = amount.ok_or(RejectReason::InvalidAmount)?;
+                self.handle_withdrawal(amount, currency, &client, trans_id)?;
+                self.set_timestamp(&client, &trans_id, transaction.timestamp);
+                Ok(Outcome::Withdrew)
+            }
+            "transfer" => {
+                let amount = amount.ok_or(RejectReason::InvalidAmount)?;
+                let destination = transaction.destination.ok_or(RejectReason::InvalidDestination)?;
+                self.handle_transfer(amount, currency, &client, destination, trans_id)?;
+                self.set_timestamp(&client, &trans_id, transaction.timestamp);
+                Ok(Outcome::Transferred)
+            }
+            "dispute" => {
+                self.handle_dispute(&client, &trans_id, part)?;
+                Ok(Outcome::Disputed)
+            }
+            "resolve" => {
+                self.handle_resolve(&client, &trans_id, part, transaction.dispute)?;
+                Ok(Outcome::Resolved)
+            }
+            "chargeback" => {
+                self.handle_chargeback(&client, &trans_id, part, transaction.dispute)?;
+                Ok(Outcome::ChargedBack)
+            }
+            _ => Err(RejectReason::UnknownType),
+        }
+    }
+
+    fn validate(&self, transaction: &Transaction, owner: Option<u16>) -> Result<(), RejectReason> {
+        // validate runs the checks whose outcome settling expired disputes cannot change, before the row moves the clock
+        // settling moves balances and can lock a client, but never unlocks or removes one
+        // what is left, e.g. insufficient funds, a tx that is not disputed or an unlock of a client that is not locked, is up to the handlers
+        let client = transaction.client;
+        let trans_type = &*transaction.trans_type;
+        let admin = matches!(trans_type, "freeze" | "unlock");
+
+        // freeze and unlock act on the lock itself, they are the only rows a locked client accepts
+        // a locked client only accepts the types the lock policy lets through, by default resolves and chargebacks of
+        // the disputes that were open when it was locked, whatever it accepts leaves it locked
+        if !admin && !self.accepts(client, trans_type) {
+            return Err(RejectReason::AccountLocked);
+        }
+
+        check_row(transaction, owner)?;
+
+        if admin {
+            if self.balances().all(|(balance_client, _, _)| balance_client != client) {
+                return Err(RejectReason::UnknownAccount);
+            }
+            if trans_type == "freeze" && self.is_locked(client) {
+                return Err(RejectReason::AccountLocked);
+            }
+            return Ok(());
+        }
+
+        // disputes act in the currency of their tx, a currency on a dispute row only has to agree with it
+        let currency = transaction.currency;
+        let is_dispute = matches!(trans_type, "dispute" | "resolve" | "chargeback");
+        if is_dispute && currency.is_some() && self.transaction(client, transaction.id).is_some_and(|status| status.currency != currency) {
+            return Err(RejectReason::CurrencyMismatch);
+        }
+        Ok(())
+    }
+
+    fn balance_of(&self, client: u16, currency: Option<Currency>) -> Option<&AccountInfo> {
+        match currency {
+            None => self.accounts.get(&client),
+            Some(currency) => self.account_in(client, currency),
+        }
+    }
+
+    fn is_locked(&self, client: u16) -> bool {
+        // every balance of a locked client is locked, checking them all also covers clients without a default balance
+        is_client_locked(self.accounts.get(&client))
+            || self.currency_accounts.get(&client).is_some_and(|balances| balances.values().any(|account| account.locked))
+    }
+
+    fn status_mut(&mut self, client: &u16, trans_id: &u32) -> Option<&mut TransactionStatus> {
+        self.transaction_status.get_mut(client).and_then(|statuses| statuses.get_mut(trans_id))
+    }
+
+    fn accepts(&self, client: u16, trans_type: &str) -> bool {
+        !self.is_locked(client) || self.config.locked_accepts.accepts(trans_type)
+    }
+
+    fn set_timestamp(&mut self, client: &u16, trans_id: &u32, timestamp: Option<u64>) {
+        if let Some(trans_status) = self.status_mut(client, trans_id) {
+            trans_status.timestamp = timestamp;
+        }
+    }
+
+    fn open_dispute(&mut self, client: &u16, trans_id: &u32, amount: Amount) {
+        // open_dispute adds a dispute of amount to the tx, handle_dispute has checked that it fits in what is not disputed yet
+        // a dispute opened with resolve_within set gets a deadline from the clock, if the clock has started
+        let deadline = self.config.resolve_within.zip(self.clock).map(|(within, now)| now.saturating_add(within));
+        let Some(trans_status) = self.status_mut(client, trans_id) else {
+            return;
+        };
+        trans_status.disputed = trans_status.disputed.checked_add(amount).expect("open disputes add up to more than their tx");
+        trans_status.opened_disputes += 1;
+        trans_status.disputes.push(Dispute { id: trans_status.opened_disputes, amount, deadline });
+        trans_status.dispute = true;
+
+        if let Some(deadline) = deadline {
+            self.deadlines.insert((deadline, *trans_id));
+        }
+    }
+
+    fn close_dispute(&mut self, client: &u16, trans_id: &u32, index: usize) -> Amount {
+        // close_dispute removes a settled dispute from the tx and returns its amount
+        // its deadline leaves the index unless another open dispute of the tx has the same one
+        let Some(trans_status) = self.status_mut(client, trans_id) else {
+            return Amount::ZERO;
+        };
+        let dispute = trans_status.disputes.remove(index);
+        trans_status.disputed = trans_status.disputed.checked_sub(dispute.amount).expect("open disputes add up to more than their tx");
+        trans_status.dispute = !trans_status.disputes.is_empty();
+        let shared = trans_status.disputes.iter().any(|open| open.deadline == dispute.deadline);
+
+        if let (Some(deadline), false) = (dispute.deadline, shared) {
+            self.deadlines.remove(&(deadline, *trans_id));
+        }
+        dispute.amount
+    }
+
+    fn settle_expired_disputes(&mut self) {
+        // settle_expired_disputes resolves or charges back the open disputes whose deadline the clock has passed
+        // the engine acts for the client, so the lock policy for its rows does not apply, a transfer's receiver is still checked
+        // a dispute that cannot be settled, e.g. its receiver does not accept it, stays open without a deadline
+        let Some(now) = self.clock else {
+            return;
+        };
+        while let Some(&(deadline, trans_id)) = self.deadlines.first() {
+            if deadline >= now {
+                return;
+            }
+            self.deadlines.pop_first();
+
+            let Some(&client) = self.tx_clients.get(&trans_id) else {
+                continue;
+            };
+
+            // every open dispute of the tx with this deadline, oldest first, they were opened at the same clock time
+            let expired = |engine: &Engine| engine.transaction(client, trans_id).and_then(|status| status.disputes.iter().position(|dispute| dispute.deadline == Some(deadline)));
+            while let Some(index) = expired(self) {
+                let first_event = self.ledger.len();
+                let settled = match self.config.on_deadline {
+                    DeadlineAction::Resolve => self.resolve_dispute(&client, &trans_id, index),
+                    DeadlineAction::Chargeback => self.charge_back_dispute(&client, &trans_id, index),
+                };
+                if settled.is_ok() {
+                    for event in &mut self.ledger[first_event..] {
+                        event.reason = Some("dispute deadline passed".to_string());
+                    }
+                } else if let Some(trans_status) = self.status_mut(&client, &trans_id) {
+                    trans_status.disputes[index].deadline = None;
+                }
+            }
+        }
+    }
+
+    fn set_charged_back(&mut self, client: &u16, trans_id: &u32, index: usize) {
+        // the tx stays in the client's history, once it is charged back in full it cannot be disputed again, even after an unlock
+        let amount = self.close_dispute(client, trans_id, index);
+        if let Some(trans_status) = self.status_mut(client, trans_id) {
+            trans_status.charged_back_amount = trans_status.charged_back_amount.checked_add(amount).expect("charged back more than the tx");
+            trans_status.charged_back = trans_status.charged_back_amount == trans_status.amount;
+        }
+    }
+
+    fn set_locked(&mut self, client: u16, locked: bool) {
+        // sets the lock on every balance of the client
+        // the lock is not a balance movement, it emits no events of its own
+        let defaults = self.accounts.get_mut(&client).into_iter();
+        let named = self.currency_accounts.get_mut(&client).into_iter().flat_map(|balances| balances.values_mut());
+        for account_info in defaults.chain(named) {
+            account_info.locked = locked;
+        }
+    }
+
+    fn commit_lock(&mut self, client: u16, trans_id: u32, kind: EventKind, locked: bool, reason: String) {
+        // commit_lock locks or unlocks every balance of the client for an admin row
+        // each balance gets an event with the reason, the balances do not change so nothing is posted to the books
+        let mut balances: Vec<(Option<Currency>, AccountInfo)> = self
+            .balances()
+            .filter(|(balance_client, _, _)| *balance_client == client)
+            .map(|(_, currency, account_info)| (currency, account_info.clone()))
+            .collect();
+        balances.sort_by_key(|(currency, _)| *currency);
+
+        for (currency, account_info) in balances {
+            self.ledger_seq += 1;
+            self.ledger.push(LedgerEvent {
+                seq: self.ledger_seq,
+                client,
+                currency,
+                tx: trans_id,
+                kind,
+                delta_available: Amount::ZERO,
+                delta_held: Amount::ZERO,
+                available: account_info.available,
+                held: account_info.held,
+                total: account_info.total,
+                locked,
+                reason: Some(reason.clone()),
+            });
+        }
+        self.set_locked(client, locked);
+    }
+
+    fn commit(&mut self, client: u16, currency: Option<Currency>, trans_id: u32, kind: EventKind, account_info: AccountInfo) -> Result<(), RejectReason> {
+        self.commit_all(currency, trans_id, vec![(client, kind, account_info)])
+    }
+
+    fn commit_all(&mut self, currency: Option<Currency>, trans_id: u32, changes: Vec<(u16, EventKind, AccountInfo)>) -> Result<(), RejectReason> {
+        // commit_all stores the new balances of accounts in one currency and emits a ledger event for each change
+        // every handler goes through here, so no balance ever moves without an event
+        // the events are also posted to the books together, nothing is stored if any of it would overflow
+        // a client can change more than once, e.g. a withdrawal and its fee, each change follows on from the one before it
+        let mut events = vec![];
+        let mut all_postings = vec![];
+        for (i, (client, kind, account_info)) in changes.iter().enumerate() {
+            let previous = changes[..i].iter().rev().find(|(changed, _, _)| changed == client).map(|(_, _, account_info)| account_info);
+            let (old_available, old_held) = previous.or_else(|| self.balance_of(*client, currency)).map_or((Amount::ZERO, Amount::ZERO), |account| (account.available, account.held));
+            let event = LedgerEvent {
+                seq: self.ledger_seq + events.len() as u64 + 1,
+                client: *client,
+                currency,
+                tx: trans_id,
+                kind: *kind,
+                delta_available: account_info.available.checked_sub(old_available).ok_or(RejectReason::Overflow)?,
+                delta_held: account_info.held.checked_sub(old_held).ok_or(RejectReason::Overflow)?,
+                available: account_info.available,
+                held: account_info.held,
+                total: account_info.total,
+                locked: account_info.locked,
+                reason: None,
+            };
+            all_postings.extend(postings(&event).ok_or(RejectReason::Overflow)?);
+            events.push(event);
+        }
+        self.books.post(currency, &all_postings).ok_or(RejectReason::Overflow)?;
+
+        self.ledger_seq += events.len() as u64;
+        self.ledger.extend(events);
+        for (client, _, account_info) in changes {
+            match currency {
+                None => self.accounts.insert(client, account_info),
+                Some(currency) => self.currency_accounts.entry(client).or_default().insert(currency, account_info),
+            };
+        }
+        Ok(())
+    }
+
+    fn handle_freeze(&mut self, client: &u16, trans_id: u32, reason: Option<String>) -> Result<(), RejectReason> {
+        // handle_freeze is an admin row that locks a client that is not locked, with the reason it is done for
+        // nothing else changes, balances and transactions stay as they are until an unlock
+        // validate has checked that the client has balances and is not locked
+        let reason = required_reason(reason)?;
+        self.commit_lock(*client, trans_id, EventKind::Freeze, true, reason);
+        Ok(())
+    }
+
+    fn handle_unlock(&mut self, client: &u16, trans_id: u32, reason: Option<String>) -> Result<(), RejectReason> {
+        // handle_unlock is an admin row that reinstates a locked client, whether a chargeback or a freeze locked it
+        // the client's transactions were kept, so it can dispute them again, except the ones that were charged back
+        // validate has checked that the client has balances
+        let reason = required_reason(reason)?;
+        if !self.is_locked(*client) {
+            return Err(RejectReason::NotLocked);
+        }
+
+        self.commit_lock(*client, trans_id, EventKind::Unlock, false, reason);
+        Ok(())
+    }
+
+    fn push_fee(&self, changes: &mut Vec<(u16, EventKind, AccountInfo)>, trans_type: &str, amount: Amount) -> Result<(), RejectReason> {
+        // push_fee adds the fee of a transaction as a change of its own after the last change, charged to the same client
+        // the fee is linked to the transaction by its tx id, no change is added when there is no fee
+        let fee = self.config.fees.charge(trans_type, amount).ok_or(RejectReason::Overflow)?;
+        if fee.is_positive() {
+            let (client, _, account_info) = changes.last().unwrap();
+            let charged = with_fee(account_info, fee)?;
+            changes.push((*client, EventKind::Fee, charged));
+        }
+        Ok(())
+    }
+
+    fn handle_chargeback(&mut self, client: &u16, trans_id: &u32, amount: Option<Amount>, dispute: Option<u32>) -> Result<(), RejectReason> {
+        // handle_chargeback reverses an open dispute of a transaction if a client exists and the transaction exists with a dispute
+        // dispute picks the open dispute with that id, amount the oldest open dispute of that amount, without either the oldest open dispute is charged back
+        let trans_status = self.transaction_status.get(client);
+        if !does_transaction_exist(trans_id, trans_status) {
+            return Err(RejectReason::UnknownTransaction);
+        }
+
+        if !does_transaction_exist_with_dispute(trans_id, trans_status) {
+            return Err(RejectReason::NotDisputed);
+        }
+
+        let index = find_dispute(trans_status.unwrap().get(trans_id).unwrap(), amount, dispute).ok_or(RejectReason::NotDisputed)?;
+        self.charge_back_dispute(client, trans_id, index)
+    }
+
+    fn charge_back_dispute(&mut self, client: &u16, trans_id: &u32, index: usize) -> Result<(), RejectReason> {
+        // charge_back_dispute reverses the disputed part of the transaction
+        // deposit: the deposit is reversed, removing the amount from held and total
+        // withdrawal: the withdrawal is reversed, the provisional credit moves from held to available
+        // once all of a withdrawal is reversed its fee is paid back, nobody pays for a withdrawal that did not happen
+        // the chargeback fee on the disputed amount is charged on top, it is never refunded
+        // chargeback will then lock the account in every currency, its transactions are kept and the part is marked charged back
+        let trans_status = self.transaction(*client, *trans_id).unwrap();
+        if let Some(destination) = trans_status.destination {
+            return self.handle_transfer_chargeback(client, trans_id, destination, index);
+        }
+
+        let currency = trans_status.currency;
+        let dispute_amount = trans_status.disputes[index].amount;
+        let reverses_all = trans_status.charged_back_amount.checked_add(dispute_amount) == Some(trans_status.amount);
+        let fee = if reverses_all { trans_status.fee } else { Amount::ZERO };
+        let current_account = self.balance_of(*client, currency).unwrap();
+        let held = current_account.held.checked_sub(dispute_amount).ok_or(RejectReason::Overflow)?;
+        let account_info = if does_deposit_transaction_exist_with_dispute(trans_id, self.transaction_status.get(client)) {
+            AccountInfo {
+                available: current_account.available,
+                held,
+                total: current_account.total.checked_sub(dispute_amount).ok_or(RejectReason::Overflow)?,
+                locked: true,
+            }
+        } else {
+            AccountInfo {
+                available: current_account.available.checked_add(dispute_amount).ok_or(RejectReason::Overflow)?,
+                held,
+                total: current_account.total,
+                locked: true,
+            }
+        };
+
+        // insert locked account, nothing should pass ever again
+        let mut changes = vec![(*client, EventKind::Chargeback, account_info)];
+        if fee.is_positive() {
+            let refunded = with_fee(&changes[0].2, fee.checked_neg().ok_or(RejectReason::Overflow)?)?;
+            changes.push((*client, EventKind::FeeRefund, refunded));
+        }
+        self.push_fee(&mut changes, "chargeback", dispute_amount)?;
+        self.commit_all(currency, *trans_id, changes)?;
+        self.set_locked(*client, true);
+        self.set_charged_back(client, trans_id, index);
+        if let Some(trans_status) = self.status_mut(client, trans_id) {
+            trans_status.fee = trans_status.fee.checked_sub(fee).ok_or(RejectReason::Overflow)?;
+        }
+        Ok(())
+    }
+
+    fn handle_resolve(&mut self, client: &u16, trans_id: &u32, amount: Option<Amount>, dispute: Option<u32>) -> Result<(), RejectReason> {
+        // handle_resolve ends an open dispute without reversing the transaction if a client exists and the transaction exists with a dispute
+        // dispute picks the open dispute with that id, amount the oldest open dispute of that amount, without either the oldest open dispute is resolved
+        let trans_status = self.transaction_status.get(client);
+        if !does_transaction_exist(trans_id, trans_status) {
+            return Err(RejectReason::UnknownTransaction);
+        }
+
+        if !does_transaction_exist_with_dispute(trans_id, trans_status) {
+            return Err(RejectReason::NotDisputed);
+        }
+
+        let index = find_dispute(trans_status.unwrap().get(trans_id).unwrap(), amount, dispute).ok_or(RejectReason::NotDisputed)?;
+        self.resolve_dispute(client, trans_id, index)
+    }
+
+    fn resolve_dispute(&mut self, client: &u16, trans_id: &u32, index: usize) -> Result<(), RejectReason> {
+        // resolve_dispute releases the disputed part of the transaction
+        // deposit: the amount moves from held back to available
+        // withdrawal: the withdrawal stands, the provisional credit is taken back out of held and total
+        // a fee charged with the tx is paid back once the resolved disputes add up to the whole amount, only the first time
+        // a client locked while the dispute was open stays locked
+        // the rest of the tx can be disputed again later
+        let trans_status = self.transaction(*client, *trans_id).unwrap();
+        if let Some(destination) = trans_status.destination {
+            return self.handle_transfer_resolve(client, trans_id, destination, index);
+        }
+
+        let currency = trans_status.currency;
+        let dispute_amount = trans_status.disputes[index].amount;
+        let is_deposit = trans_status.deposit;
+        // until the resolved disputes add up to the whole tx the fee stays where it is
+        let resolved = trans_status.resolved_amount.checked_add(dispute_amount).ok_or(RejectReason::Overflow)?;
+        let fee = if resolved >= trans_status.amount { trans_status.fee } else { Amount::ZERO };
+        let current_account = self.balance_of(*client, currency).unwrap();
+        let held = current_account.held.checked_sub(dispute_amount).ok_or(RejectReason::Overflow)?;
+        let account_info = if is_deposit {
+            AccountInfo {
+                available: current_account.available.checked_add(dispute_amount).ok_or(RejectReason::Overflow)?,
+                held,
+                total: current_account.total,
+                locked: current_account.locked,
+            }
+        } else {
+            AccountInfo {
+                available: current_account.available,
+                held,
+                total: current_account.total.checked_sub(dispute_amount).ok_or(RejectReason::Overflow)?,
+                locked: current_account.locked,
+            }
+        };
+
+        let mut changes = vec![(*client, EventKind::Resolve, account_info)];
+        if fee.is_positive() {
+            let refunded = with_fee(&changes[0].2, fee.checked_neg().ok_or(RejectReason::Overflow)?)?;
+            changes.push((*client, EventKind::FeeRefund, refunded));
+        }
+        self.commit_all(currency, *trans_id, changes)?;
+        self.close_dispute(client, trans_id, index);
+        if let Some(trans_status) = self.status_mut(client, trans_id) {
+            trans_status.fee = trans_status.fee.checked_sub(fee).ok_or(RejectReason::Overflow)?;
+            trans_status.resolved_amount = resolved;
+        }
+        Ok(())
+    }
+
+    fn handle_dispute(&mut self, client: &u16, trans_id: &u32, amount: Option<Amount>) -> Result<(), RejectReason> {
+        // handle_dispute holds the disputed amount if a client exists and the transaction exists
+        // the amount is part of the tx, at most what is neither disputed nor charged back yet
+        // without one the dispute is of all of that, and only if no other dispute of the tx is open
+        // deposit: the amount moves from available to held
+        // withdrawal: the amount is provisionally credited to held, or rejected, depending on config
+        // this will also update the transactions map in the case there is a future resolve/chargeback
+        let trans_status = self.transaction_status.get(client);
+        if !does_transaction_exist(trans_id, trans_status) {
+            return Err(RejectReason::UnknownTransaction);
+        }
+
+        if amount.is_none() && !does_transaction_exist_without_dispute(trans_id, trans_status) {
+            return Err(RejectReason::AlreadyDisputed);
+        }
+
+        let trans_status = trans_status.unwrap().get(trans_id).unwrap();
+        if trans_status.charged_back {
+            return Err(RejectReason::NotDisputable);
+        }
+
+        let undisputed = trans_status
+            .amount
+            .checked_sub(trans_status.disputed)
+            .and_then(|rest| rest.checked_sub(trans_status.charged_back_amount))
+            .ok_or(RejectReason::Overflow)?;
+        if !undisputed.is_positive() {
+            return Err(RejectReason::AlreadyDisputed);
+        }
+
+        let dispute_amount = amount.unwrap_or(undisputed);
+        if dispute_amount > undisputed {
+            return Err(RejectReason::ExceedsUndisputed);
+        }
+
+        // the window is measured against the clock, a tx without a timestamp has no age
+        if let (Some(window), Some(now), Some(timestamp)) = (self.config.dispute_window, self.clock, trans_status.timestamp) {
+            if now.saturating_sub(timestamp) > window {
+                return Err(RejectReason::DisputeWindowClosed);
+            }
+        }
+
+        if let Some(destination) = trans_status.destination {
+            return self.handle_transfer_dispute(client, trans_id, destination, dispute_amount);
+        }
+
+        let currency = trans_status.currency;
+        let is_deposit = trans_status.deposit;
+        let current_account = self.balance_of(*client, currency).unwrap();
+        let held = current_account.held.checked_add(dispute_amount).ok_or(RejectReason::Overflow)?;
+        let account_info = if is_deposit {
+            AccountInfo {
+                available: current_account.available.checked_sub(dispute_amount).ok_or(RejectReason::Overflow)?,
+                held,
+                total: current_account.total,
+                locked: current_account.locked,
+            }
+        } else {
+            if self.config.withdrawal_disputes == WithdrawalDisputes::Reject {
+                return Err(RejectReason::NotDisputable);
+            }
+
+            AccountInfo {
+                available: current_account.available,
+                held,
+                total: current_account.total.checked_add(dispute_amount).ok_or(RejectReason::Overflow)?,
+                locked: current_account.locked,
+            }
+        };
+
+        self.commit(*client, currency, *trans_id, EventKind::Dispute, account_info)?;
+        self.open_dispute(client, trans_id, dispute_amount);
+        Ok(())
+    }
+
+    fn handle_transfer_chargeback(&mut self, client: &u16, trans_id: &u32, destination: u16, index: usize) -> Result<(), RejectReason> {
+        // handle_transfer_chargeback reverses a disputed part of a transfer, the held amount goes back from the receiver to the sender
+        // the sender charged back, so like every chargeback it locks the sender and is charged the chargeback fee
+        // the receiver only loses the amount, it is not locked, a receiver locked since the dispute has to accept chargebacks, as it does by default
+        if !self.accepts(destination, "chargeback") {
+            return Err(RejectReason::AccountLocked);
+        }
+
+        let trans_status = self.transaction(*client, *trans_id).unwrap();
+        let (currency, amount) = (trans_status.currency, trans_status.disputes[index].amount);
+        // the receiver has to be here, for a transfer's dispute and resolve as for its chargeback, a sharded engine lends it to the sender's shard
+        let receiver = self.balance_of(destination, currency).ok_or(RejectReason::UnknownAccount)?;
+        let receiver_info = AccountInfo {
+            available: receiver.available,
+            held: receiver.held.checked_sub(amount).ok_or(RejectReason::Overflow)?,
+            total: receiver.total.checked_sub(amount).ok_or(RejectReason::Overflow)?,
+            locked: receiver.locked,
+        };
+        let sender = self.balance_of(*client, currency).unwrap();
+        let sender_info = AccountInfo {
+            available: sender.available.checked_add(amount).ok_or(RejectReason::Overflow)?,
+            held: sender.held,
+            total: sender.total.checked_add(amount).ok_or(RejectReason::Overflow)?,
+            locked: true,
+        };
+
+        let mut changes = vec![(destination, EventKind::Chargeback, receiver_info), (*client, EventKind::Chargeback, sender_info)];
+        self.push_fee(&mut changes, "chargeback", amount)?;
+        self.commit_all(currency, *trans_id, changes)?;
+        self.set_locked(*client, true);
+        self.set_charged_back(client, trans_id, index);
+        Ok(())
+    }
+
+    fn handle_transfer_resolve(&mut self, client: &u16, trans_id: &u32, destination: u16, index: usize) -> Result<(), RejectReason> {
+        // handle_transfer_resolve ends a dispute of a transfer, the transfer stands
+        // the amount moves from held back to available at the receiver, which stays locked if it was locked since the dispute
+        // a locked receiver has to accept resolves, as it does by default
+        if !self.accepts(destination, "resolve") {
+            return Err(RejectReason::AccountLocked);
+        }
+
+        let trans_status = self.transaction(*client, *trans_id).unwrap();
+        let (currency, amount) = (trans_status.currency, trans_status.disputes[index].amount);
+        let receiver = self.balance_of(destination, currency).ok_or(RejectReason::UnknownAccount)?;
+        let receiver_info = AccountInfo {
+            available: receiver.available.checked_add(amount).ok_or(RejectReason::Overflow)?,
+            held: receiver.held.checked_sub(amount).ok_or(RejectReason::Overflow)?,
+            total: receiver.total,
+            locked: receiver.locked,
+        };
+
+        self.commit(destination, currency, *trans_id, EventKind::Resolve, receiver_info)?;
+        self.close_dispute(client, trans_id, index);
+        Ok(())
+    }
+
+    fn handle_transfer_dispute(&mut self, client: &u16, trans_id: &u32, destination: u16, amount: Amount) -> Result<(), RejectReason> {
+        // handle_transfer_dispute holds the part of a transfer the sender disputes
+        // the money is with the receiver, so the amount moves from available to held at the receiver, like a disputed deposit
+        // the sender's balance does not change until a chargeback gives the amount back
+        // a locked receiver only has funds held if locked clients accept disputes
+        if !self.accepts(destination, "dispute") {
+            return Err(RejectReason::AccountLocked);
+        }
+
+        let currency = self.transaction(*client, *trans_id).unwrap().currency;
+        let receiver = self.balance_of(destination, currency).ok_or(RejectReason::UnknownAccount)?;
+        let receiver_info = AccountInfo {
+            available: receiver.available.checked_sub(amount).ok_or(RejectReason::Overflow)?,
+            held: receiver.held.checked_add(amount).ok_or(RejectReason::Overflow)?,
+            total: receiver.total,
+            locked: receiver.locked,
+        };
+
+        self.commit(destination, currency, *trans_id, EventKind::Dispute, receiver_info)?;
+        self.open_dispute(client, trans_id, amount);
+        Ok(())
+    }
+
+    fn handle_transfer(&mut self, amount: Amount, currency: Option<Currency>, client: &u16, destination: u16, trans_id: u32) -> Result<(), RejectReason> {
+        // handle_transfer moves available funds from client to destination, both sides change or neither does
+        // the sender needs enough available funds in the currency, the destination gets a balance in it if it has none
+        // a locked destination only receives if locked clients accept deposits, a locked sender was already checked for transfers
+        // the transfer is kept with the sender, who can dispute it later
+        if !self.accepts(destination, "deposit") {
+            return Err(RejectReason::AccountLocked);
+        }
+
+        let sender = self.balance_of(*client, currency).ok_or(RejectReason::UnknownAccount)?;
+        if sender.available < amount {
+            return Err(RejectReason::InsufficientFunds);
+        }
+
+        let sender_info = AccountInfo {
+            available: sender.available.checked_sub(amount).ok_or(RejectReason::Overflow)?,
+            held: sender.held,
+            total: sender.total.checked_sub(amount).ok_or(RejectReason::Overflow)?,
+            locked: sender.locked,
+        };
+        let receiver_info = match self.balance_of(destination, currency) {
+            None => AccountInfo { available: amount, held: Amount::ZERO, total: amount, locked: self.is_locked(destination) },
+            Some(receiver) => AccountInfo {
+                available: receiver.available.checked_add(amount).ok_or(RejectReason::Overflow)?,
+                held: receiver.held,
+                total: receiver.total.checked_add(amount).ok_or(RejectReason::Overflow)?,
+                locked: receiver.locked,
+            },
+        };
+
+        let changes = vec![(*client, EventKind::TransferOut, sender_info), (destination, EventKind::TransferIn, receiver_info)];
+        self.commit_all(currency, trans_id, changes)?;
+        let trans_status = TransactionStatus { amount, currency, destination: Some(destination), ..Default::default() };
+        self.transaction_status.entry(*client).or_default().insert(trans_id, trans_status);
+        Ok(())
+    }
+
+    fn handle_withdrawal(&mut self, amount: Amount, currency: Option<Currency>, client: &u16, trans_id: u32) -> Result<(), RejectReason> {
+        // handle_withdrawal only withdraws if a client exists with enough available funds in the currency
+        // a client that never deposited in the currency is unknown to it, whatever else it holds
+        // the withdrawal fee is taken from the same balance, the funds have to cover the amount and the fee
+        // withdrawal will then update the account map (client -> account) with new numbers withdrawn
+        // this will also update the transactions map in the case there is a future dispute/resolve/chargeback
+        let fee = self.config.fees.charge("withdrawal", amount).ok_or(RejectReason::Overflow)?;
+        let trans_status = TransactionStatus { amount, currency, fee, ..Default::default() };
+
+        let current_account = self.balance_of(*client, currency).ok_or(RejectReason::UnknownAccount)?;
+        if current_account.available < amount.checked_add(fee).ok_or(RejectReason::Overflow)? {
+            return Err(RejectReason::InsufficientFunds);
+        }
+
+        let available = current_account.available.checked_sub(amount).ok_or(RejectReason::Overflow)?;
+        let total = current_account.total.checked_sub(amount).ok_or(RejectReason::Overflow)?;
+        let account_info = AccountInfo {
+            available,
+            held: current_account.held,
+            total,
+            locked: current_account.locked,
+        };
+
+        let mut changes = vec![(*client, EventKind::Withdrawal, account_info)];
+        self.push_fee(&mut changes, "withdrawal", amount)?;
+        self.commit_all(currency, trans_id, changes)?;
+        // a client whose funds all came in through transfers has no transactions yet
+        self.transaction_status.entry(*client).or_default().insert(trans_id, trans_status);
+        Ok(())
+    }
+
+    fn handle_deposit(&mut self, amount: Amount, currency: Option<Currency>, client: &u16, trans_id: u32) -> Result<(), RejectReason> {
+        // handle_deposit creates client if client does not exist, or its balance in the currency if it has none yet
+        // deposit will then update the account map (client -> account) with new numbers deposited
+        // this will also update the transactions map in the case there is a future dispute/resolve/chargeback
+        // a deposit that would overflow the account balance is rejected
+        // a deposit into a locked client, if the lock policy accepts it, leaves it locked, a new balance included
+        let trans_status = TransactionStatus { amount, deposit: true, currency, ..Default::default() };
+        let account_info = match self.balance_of(*client, currency) {
+            None => AccountInfo {
+                available: amount,
+                held: Amount::ZERO,
+                total: amount,
+                locked: self.is_locked(*client),
+            },
+            Some(current_account) => AccountInfo {
+                available: current_account.available.checked_add(amount).ok_or(RejectReason::Overflow)?,
+                held: current_account.held,
+                total: current_account.total.checked_add(amount).ok_or(RejectReason::Overflow)?,
+                locked: current_account.locked,
+            },
+        };
+
+        self.commit(*client, currency, trans_id, EventKind::Deposit, account_info)?;
+        self.transaction_status.entry(*client).or_default().insert(trans_id, trans_status);
+        Ok(())
+    }
+}
+
+pub(crate) fn index_trans_id(tx_clients: &mut HashMap<u32, u16>, transaction: &Transaction) -> Result<Option<u16>, RejectReason> {
+    // index_trans_id looks up the client a tx id belongs to, expects the type to be lowercase already
+    // deposits, withdrawals, transfers and the admin freeze and unlock rows claim their tx id first, a tx id can only be claimed once
+    // the id is claimed even when the transaction is rejected later on, a transfer's id belongs to the sender
+    if matches!(&*transaction.trans_type, "deposit" | "withdrawal" | "transfer" | "freeze" | "unlock") {
+        if tx_clients.contains_key(&transaction.id) {
+            return Err(RejectReason::DuplicateTransaction);
+        }
+
+        tx_clients.insert(transaction.id, transaction.client);
+    }
+
+    Ok(tx_clients.get(&transaction.id).copied())
+}
+
+fn does_transaction_exist_without_dispute(trans_id: &u32, trans_status: Option<&HashMap<u32, TransactionStatus>>) -> bool {
+    // used to find a transaction exists for a dispute record based on the transaction id
+    does_transaction_exist(trans_id, trans_status) && !trans_status.unwrap().get(trans_id).unwrap().dispute
+}
+
+fn does_deposit_transaction_exist_with_dispute(trans_id: &u32, trans_status: Option<&HashMap<u32, TransactionStatus>>) -> bool {
+    // used to find a deposit transaction exists for a chargeback record based on the transaction id
+    does_transaction_exist_with_dispute(trans_id, trans_status) && trans_status.unwrap().get(trans_id).unwrap().deposit
+}
+
+fn does_transaction_exist_with_dispute(trans_id: &u32, trans_status: Option<&HashMap<u32, TransactionStatus>>) -> bool {
+    // used to find a transaction exists for a resolve record based on the transaction id
+    does_transaction_exist(trans_id, trans_status) && trans_status.unwrap().get(trans_id).unwrap().dispute
+}
+
+fn find_dispute(trans_status: &TransactionStatus, amount: Option<Amount>, id: Option<u32>) -> Option<usize> {
+    // used to find the open dispute a resolve or chargeback record settles, the one with the id if it names one,
+    // the oldest one of the amount if it has one, an amount given with an id has to be that dispute's
+    trans_status
+        .disputes
+        .iter()
+        .position(|dispute| id.is_none_or(|id| dispute.id == id) && amount.is_none_or(|amount| dispute.amount == amount))
+}
+
+fn does_transaction_exist(trans_id: &u32, trans_status: Option<&HashMap<u32, TransactionStatus>>) -> bool {
+    // used to find a transaction exists for a dispute record
+    trans_status.is_some_and(|statuses| statuses.contains_key(trans_id))
+}
+
+pub(crate) fn check_row(transaction: &Transaction, owner: Option<u16>) -> Result<(), RejectReason> {
+    // check_row is what a row can be rejected for on its own, without looking at any balance, lock or tx
+    // disputes name the client and the tx, both have to agree with the index
+    // a deposit, withdrawal or transfer with a missing or not positive amount is invalid, a dispute with a negative one too
+    let client = transaction.client;
+    let positive = transaction.amount.is_some_and(|amount| amount.is_positive());
+    let negative = transaction.amount.is_some_and(|amount| amount != Amount::ZERO && !amount.is_positive());
+    match &*transaction.trans_type {
+        "freeze" | "unlock" => required_reason(transaction.reason.clone()).map(drop),
+        _ if owner.is_some_and(|owner| owner != client) => Err(RejectReason::ClientMismatch),
+        "deposit" | "withdrawal" | "transfer" if !positive => Err(RejectReason::InvalidAmount),
+        "transfer" if transaction.destination.is_none_or(|destination| destination == client) => Err(RejectReason::InvalidDestination),
+        "dispute" | "resolve" | "chargeback" if negative => Err(RejectReason::InvalidAmount),
+        "deposit" | "withdrawal" | "transfer" | "dispute" | "resolve" | "chargeback" => Ok(()),
+        _ => Err(RejectReason::UnknownType),
+    }
+}
+
+fn required_reason(reason: Option<String>) -> Result<String, RejectReason> {
+    // admin rows have to say why they were done, a blank reason is no reason
+    reason.map(|reason| reason.trim().to_string()).filter(|reason| !reason.is_empty()).ok_or(RejectReason::MissingReason)
+}
+
+fn with_fee(account_info: &AccountInfo, fee: Amount) -> Result<AccountInfo, RejectReason> {
+    // the balances after a fee is taken from available, a negative fee pays it back
+    Ok(AccountInfo {
+        available: account_info.available.checked_sub(fee).ok_or(RejectReason::Overflow)?,
+        held: account_info.held,
+        total: account_info.total.checked_sub(fee).ok_or(RejectReason::Overflow)?,
+        locked: account_info.locked,
+    })
+}
+
+fn is_client_locked(account: Option<&AccountInfo>) -> bool {
+    account.is_some_and(|account| account.locked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::books::BookAccount;
+    use crate::test_support::transaction;
+
+    fn to_amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_apply_returns_outcome_per_transaction_type() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.apply(transaction("deposit", 1, 1, "5.0")), Ok(Outcome::Deposited));
+        assert_eq!(engine.apply(transaction("withdrawal", 1, 2, "1.0")), Ok(Outcome::Withdrew));
+        assert_eq!(engine.apply(transaction("dispute", 1, 1, "0")), Ok(Outcome::Disputed));
+        assert_eq!(engine.apply(transaction("resolve", 1, 1, "0")), Ok(Outcome::Resolved));
+        assert_eq!(engine.apply(transaction("dispute", 1, 1, "0")), Ok(Outcome::Disputed));
+        assert_eq!(engine.apply(transaction("chargeback", 1, 1, "0")), Ok(Outcome::ChargedBack));
+    }
+
+    #[test]
+    fn test_apply_rejected_leaves_state_untouched() {
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.apply(transaction("withdrawal", 1, 1, "1.0")), Err(TxError::Rejected(RejectReason::UnknownAccount)));
+        assert_eq!(engine.apply(transaction("refund", 1, 2, "1.0")), Err(TxError::Rejected(RejectReason::UnknownType)));
+        assert_eq!(engine.apply(transaction("deposit", 1, 3, "0")), Err(TxError::Rejected(RejectReason::InvalidAmount)));
+        assert_eq!(engine.apply(transaction("deposit", 1, 4, "")), Err(TxError::Rejected(RejectReason::InvalidAmount)));
+        assert_eq!(engine.apply(transaction("withdrawal", 1, 5, "-1.0")), Err(TxError::Rejected(RejectReason::InvalidAmount)));
+        assert!(engine.account(1).is_none());
+        assert_eq!(engine.accounts().count(), 0);
+    }
+
+    #[test]
+    fn test_apply_rejects_locked_account() {
+        let mut engine = Engine::new();
+
+        engine.apply(transaction("deposit", 1, 1, "1.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "0")).unwrap();
+        engine.apply(transaction("chargeback", 1, 1, "0")).unwrap();
+
+        assert_eq!(engine.apply(transaction("deposit", 1, 2, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(transaction("dispute", 1, 1, "0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+    }
+
+    #[test]
+    fn test_apply_rejects_balance_overflow() {
+        let mut engine = Engine::new();
+
+        engine.apply(transaction("deposit", 1, 1, "922337203685477.5807")).unwrap();
+
+        assert_eq!(engine.apply(transaction("deposit", 1, 2, "0.0001")), Err(TxError::Rejected(RejectReason::Overflow)));
+        assert_eq!(engine.account(1).unwrap().total.to_string(), "922337203685477.5807");
+    }
+
+    fn in_currency(trans_type: &str, client: u16, id: u32, amount: &str, currency: &str) -> Transaction {
+        Transaction { currency: Some(currency.parse().unwrap()), ..transaction(trans_type, client, id, amount) }
+    }
+
+    #[test]
+    fn test_apply_keeps_a_balance_per_currency() {
+        let mut engine = Engine::new();
+        let eur: Currency = "EUR".parse().unwrap();
+
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(in_currency("deposit", 1, 2, "3.0", "eur")).unwrap();
+        engine.apply(in_currency("withdrawal", 1, 3, "1.0", "EUR")).unwrap();
+
+        assert_eq!(engine.apply(in_currency("withdrawal", 1, 4, "5.0", "EUR")), Err(TxError::Rejected(RejectReason::InsufficientFunds)));
+        assert_eq!(engine.apply(in_currency("withdrawal", 1, 5, "1.0", "USD")), Err(TxError::Rejected(RejectReason::UnknownAccount)));
+        assert_eq!(engine.account(1).unwrap().available, to_amount("10.0"));
+        assert_eq!(engine.account_in(1, eur).unwrap().available, to_amount("2.0"));
+        assert_eq!(engine.balances().count(), 2);
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_apply_disputes_in_the_currency_of_the_tx() {
+        let mut engine = Engine::new();
+        let eur: Currency = "EUR".parse().unwrap();
+
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(in_currency("deposit", 1, 2, "3.0", "EUR")).unwrap();
+
+        // the dispute row does not have to name the currency, but it cannot name another one
+        assert_eq!(engine.apply(in_currency("dispute", 1, 2, "", "USD")), Err(TxError::Rejected(RejectReason::CurrencyMismatch)));
+        assert_eq!(engine.apply(in_currency("dispute", 1, 1, "", "EUR")), Err(TxError::Rejected(RejectReason::CurrencyMismatch)));
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+
+        assert_eq!(engine.account_in(1, eur).unwrap().held, to_amount("3.0"));
+        assert_eq!(engine.account(1).unwrap().held, Amount::ZERO);
+        assert_eq!(engine.transaction(1, 2).unwrap().currency, Some(eur));
+
+        engine.apply(in_currency("resolve", 1, 2, "", "eur")).unwrap();
+        assert_eq!(engine.account_in(1, eur).unwrap().available, to_amount("3.0"));
+    }
+
+    #[test]
+    fn test_apply_chargeback_locks_every_currency() {
+        let mut engine = Engine::new();
+
+        engine.apply(in_currency("deposit", 1, 1, "10.0", "USD")).unwrap();
+        engine.apply(in_currency("deposit", 1, 2, "3.0", "EUR")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("chargeback", 1, 2, "")).unwrap();
+
+        // no default balance, the lock is still found
+        assert!(engine.account(1).is_none());
+        assert!(engine.balances().all(|(_, _, account_info)| account_info.locked));
+        assert_eq!(engine.apply(transaction("deposit", 1, 3, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(in_currency("withdrawal", 1, 4, "1.0", "USD")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    fn transfer(client: u16, destination: u16, id: u32, amount: &str) -> Transaction {
+        Transaction { destination: Some(destination), ..transaction("transfer", client, id, amount) }
+    }
+
+    // client 1 sends 4.0 of its 10.0 to client 2, which already holds 1.0
+    fn engine_with_transfer() -> Engine {
+        let mut engine = Engine::new();
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("deposit", 2, 2, "1.0")).unwrap();
+        assert_eq!(engine.apply(transfer(1, 2, 3, "4.0")), Ok(Outcome::Transferred));
+        engine
+    }
+
+    fn balances(engine: &Engine, client: u16) -> (Amount, Amount, Amount, bool) {
+        let account_info = engine.account(client).unwrap();
+        (account_info.available, account_info.held, account_info.total, account_info.locked)
+    }
+
+    #[test]
+    fn test_handle_transfer_moves_funds_between_clients() {
+        let mut engine = engine_with_transfer();
+
+        assert_eq!(balances(&engine, 1), (to_amount("6.0"), Amount::ZERO, to_amount("6.0"), false));
+        assert_eq!(balances(&engine, 2), (to_amount("5.0"), Amount::ZERO, to_amount("5.0"), false));
+        // the transfer is the sender's, the receiver is a new account if it had none
+        assert_eq!(engine.transaction(1, 3).unwrap().destination, Some(2));
+        assert!(engine.transaction(2, 3).is_none());
+        engine.apply(transfer(2, 3, 4, "5.0")).unwrap();
+        assert_eq!(balances(&engine, 3), (to_amount("5.0"), Amount::ZERO, to_amount("5.0"), false));
+
+        // funds that came in by transfer can be withdrawn
+        engine.apply(transaction("withdrawal", 3, 5, "2.0")).unwrap();
+        let kinds: Vec<EventKind> = engine.ledger()[2..].iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, vec![EventKind::TransferOut, EventKind::TransferIn, EventKind::TransferOut, EventKind::TransferIn, EventKind::Withdrawal]);
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_handle_transfer_rejections_change_nothing() {
+        let mut engine = engine_with_transfer();
+        engine.apply(transaction("deposit", 3, 4, "1.0")).unwrap();
+        engine.apply(transaction("dispute", 3, 4, "")).unwrap();
+        engine.apply(transaction("chargeback", 3, 4, "")).unwrap();
+        let ledger_len = engine.ledger().len();
+
+        assert_eq!(engine.apply(transfer(1, 2, 10, "6.0001")), Err(TxError::Rejected(RejectReason::InsufficientFunds)));
+        assert_eq!(engine.apply(transfer(4, 2, 11, "1.0")), Err(TxError::Rejected(RejectReason::UnknownAccount)));
+        assert_eq!(engine.apply(transaction("transfer", 1, 12, "1.0")), Err(TxError::Rejected(RejectReason::InvalidDestination)));
+        assert_eq!(engine.apply(transfer(1, 1, 13, "1.0")), Err(TxError::Rejected(RejectReason::InvalidDestination)));
+        assert_eq!(engine.apply(transfer(1, 2, 14, "0")), Err(TxError::Rejected(RejectReason::InvalidAmount)));
+        // locks are respected on both sides
+        assert_eq!(engine.apply(transfer(1, 3, 15, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(transfer(3, 1, 16, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(transfer(1, 2, 3, "1.0")), Err(TxError::Rejected(RejectReason::DuplicateTransaction)));
+
+        assert_eq!(engine.ledger().len(), ledger_len);
+        assert_eq!(balances(&engine, 1), (to_amount("6.0"), Amount::ZERO, to_amount("6.0"), false));
+        assert_eq!(balances(&engine, 2), (to_amount("5.0"), Amount::ZERO, to_amount("5.0"), false));
+    }
+
+    #[test]
+    fn test_handle_transfer_dispute_holds_at_the_receiver() {
+        let mut engine = engine_with_transfer();
+
+        // only the sender can dispute, the tx is its own
+        assert_eq!(engine.apply(transaction("dispute", 2, 3, "")), Err(TxError::Rejected(RejectReason::ClientMismatch)));
+        engine.apply(transaction("dispute", 1, 3, "")).unwrap();
+
+        assert_eq!(balances(&engine, 1), (to_amount("6.0"), Amount::ZERO, to_amount("6.0"), false));
+        assert_eq!(balances(&engine, 2), (to_amount("1.0"), to_amount("4.0"), to_amount("5.0"), false));
+        assert!(engine.transaction(1, 3).unwrap().dispute);
+        assert_eq!(engine.apply(transaction("dispute", 1, 3, "")), Err(TxError::Rejected(RejectReason::AlreadyDisputed)));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_handle_transfer_resolve_lets_the_transfer_stand() {
+        let mut engine = engine_with_transfer();
+        engine.apply(transaction("dispute", 1, 3, "")).unwrap();
+        engine.apply(transaction("resolve", 1, 3, "")).unwrap();
+
+        assert_eq!(balances(&engine, 1), (to_amount("6.0"), Amount::ZERO, to_amount("6.0"), false));
+        assert_eq!(balances(&engine, 2), (to_amount("5.0"), Amount::ZERO, to_amount("5.0"), false));
+        assert!(!engine.transaction(1, 3).unwrap().dispute);
+        assert_eq!(engine.apply(transaction("chargeback", 1, 3, "")), Err(TxError::Rejected(RejectReason::NotDisputed)));
+    }
+
+    #[test]
+    fn test_handle_transfer_chargeback_returns_funds_to_the_sender() {
+        let mut engine = engine_with_transfer();
+        engine.apply(transaction("withdrawal", 2, 4, "3.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 3, "")).unwrap();
+        engine.apply(transaction("chargeback", 1, 3, "")).unwrap();
+
+        // the sender charged back, it gets the amount back and is locked like after any chargeback
+        assert_eq!(balances(&engine, 1), (to_amount("10.0"), Amount::ZERO, to_amount("10.0"), true));
+        // the receiver had spent part of it, it is left short but not locked
+        assert_eq!(balances(&engine, 2), (to_amount("-2.0"), Amount::ZERO, to_amount("-2.0"), false));
+        assert!(engine.transaction(1, 3).unwrap().charged_back);
+        assert_eq!(engine.check_books(), Ok(()));
+        assert_eq!(engine.books().balance(BookAccount::ChargebackLosses), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_handle_transfer_dispute_rejected_for_locked_receiver() {
+        let mut engine = engine_with_transfer();
+        engine.apply(transaction("dispute", 2, 2, "")).unwrap();
+        engine.apply(transaction("chargeback", 2, 2, "")).unwrap();
+
+        assert_eq!(engine.apply(transaction("dispute", 1, 3, "")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert!(!engine.transaction(1, 3).unwrap().dispute);
+    }
+
+    fn engine_with_fees(fees: &[&str]) -> Engine {
+        let mut config = EngineConfig::default();
+        for fee in fees {
+            config.fees.set(fee).unwrap();
+        }
+        Engine::with_config(config)
+    }
+
+    #[test]
+    fn test_handle_withdrawal_charges_its_fee() {
+        let mut engine = engine_with_fees(&["withdrawal=0:0.5,100:1%"]);
+        engine.apply(transaction("deposit", 1, 1, "210.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 2, "100.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 3, "10.0")).unwrap();
+
+        assert_eq!(balances(&engine, 1), (to_amount("98.5"), Amount::ZERO, to_amount("98.5"), false));
+        assert_eq!(engine.transaction(1, 2).unwrap().fee, to_amount("1.0"));
+        let kinds: Vec<(u32, EventKind)> = engine.ledger().iter().map(|event| (event.tx, event.kind)).collect();
+        assert_eq!(
+            kinds,
+            vec![(1, EventKind::Deposit), (2, EventKind::Withdrawal), (2, EventKind::Fee), (3, EventKind::Withdrawal), (3, EventKind::Fee)]
+        );
+        assert_eq!(engine.books().balance(BookAccount::FeeIncome), to_amount("-1.5"));
+        assert_eq!(engine.check_books(), Ok(()));
+
+        // the balance has to cover the fee too
+        assert_eq!(engine.apply(transaction("withdrawal", 1, 4, "98.1")), Err(TxError::Rejected(RejectReason::InsufficientFunds)));
+        engine.apply(transaction("withdrawal", 1, 5, "98.0")).unwrap();
+        assert_eq!(balances(&engine, 1), (Amount::ZERO, Amount::ZERO, Amount::ZERO, false));
+    }
+
+    #[test]
+    fn test_handle_resolve_refunds_the_fee_once() {
+        let mut engine = engine_with_fees(&["withdrawal=2"]);
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 2, "5.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("3.0"), to_amount("5.0"), to_amount("8.0"), false));
+
+        engine.apply(transaction("resolve", 1, 2, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("5.0"), Amount::ZERO, to_amount("5.0"), false));
+        assert_eq!(engine.ledger().last().unwrap().kind, EventKind::FeeRefund);
+        assert_eq!(engine.transaction(1, 2).unwrap().fee, Amount::ZERO);
+
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("resolve", 1, 2, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("5.0"), Amount::ZERO, to_amount("5.0"), false));
+        assert_eq!(engine.books().balance(BookAccount::FeeIncome), Amount::ZERO);
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_handle_chargeback_charges_its_fee() {
+        let mut engine = engine_with_fees(&["withdrawal=1", "chargeback=10%"]);
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("deposit", 1, 2, "20.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("chargeback", 1, 2, "")).unwrap();
+
+        // the chargeback fee is on the charged back amount, the withdrawal fee does not apply
+        assert_eq!(balances(&engine, 1), (to_amount("8.0"), Amount::ZERO, to_amount("8.0"), true));
+        let last: Vec<(u32, EventKind, bool)> = engine.ledger()[3..].iter().map(|event| (event.tx, event.kind, event.locked)).collect();
+        assert_eq!(last, vec![(2, EventKind::Chargeback, true), (2, EventKind::Fee, true)]);
+        assert_eq!(engine.check_books(), Ok(()));
+
+        // a transfer chargeback charges the sender that filed it
+        let mut engine = engine_with_fees(&["chargeback=0.5"]);
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transfer(1, 2, 2, "4.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("chargeback", 1, 2, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("9.5"), Amount::ZERO, to_amount("9.5"), true));
+        assert_eq!(balances(&engine, 2), (Amount::ZERO, Amount::ZERO, Amount::ZERO, false));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_handle_chargeback_refunds_the_withdrawal_fee() {
+        let mut engine = engine_with_fees(&["withdrawal=1", "chargeback=0.5"]);
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 2, "5.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("chargeback", 1, 2, "")).unwrap();
+
+        // the withdrawal never happened, its fee comes back and the chargeback fee is charged
+        assert_eq!(balances(&engine, 1), (to_amount("9.5"), Amount::ZERO, to_amount("9.5"), true));
+        let last: Vec<EventKind> = engine.ledger()[4..].iter().map(|event| event.kind).collect();
+        assert_eq!(last, vec![EventKind::Chargeback, EventKind::FeeRefund, EventKind::Fee]);
+        assert_eq!(engine.transaction(1, 2).unwrap().fee, Amount::ZERO);
+        assert_eq!(engine.check_books(), Ok(()));
+
+        // charged back in parts, the fee comes back with the part that reverses the rest of it
+        let mut engine = engine_with_fees(&["withdrawal=1"]);
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 2, "5.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "2.0")).unwrap();
+        engine.apply(transaction("chargeback", 1, 2, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("6.0"), Amount::ZERO, to_amount("6.0"), true));
+        engine.apply(admin("unlock", 1, 3, "first part reviewed")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("chargeback", 1, 2, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("10.0"), Amount::ZERO, to_amount("10.0"), true));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    fn admin(trans_type: &str, client: u16, id: u32, reason: &str) -> Transaction {
+        Transaction { reason: Some(reason.to_string()), ..transaction(trans_type, client, id, "") }
+    }
+
+    #[test]
+    fn test_handle_unlock_reinstates_a_charged_back_client() {
+        let mut engine = Engine::new();
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("deposit", 1, 2, "5.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("chargeback", 1, 2, "")).unwrap();
+
+        assert_eq!(engine.apply(transaction("unlock", 1, 3, "")), Err(TxError::Rejected(RejectReason::MissingReason)));
+        assert_eq!(engine.apply(admin("unlock", 1, 4, "  ")), Err(TxError::Rejected(RejectReason::MissingReason)));
+        assert_eq!(engine.apply(admin("unlock", 1, 5, " chargeback reviewed ")), Ok(Outcome::Unlocked));
+        assert_eq!(balances(&engine, 1), (to_amount("10.0"), Amount::ZERO, to_amount("10.0"), false));
+        let event = engine.ledger().last().unwrap();
+        assert_eq!((event.tx, event.kind, event.locked), (5, EventKind::Unlock, false));
+        assert_eq!(event.reason.as_deref(), Some("chargeback reviewed"));
+        assert_eq!(engine.apply(admin("unlock", 1, 6, "again")), Err(TxError::Rejected(RejectReason::NotLocked)));
+
+        // the history is still there, the charged back tx is settled for good
+        engine.apply(transaction("deposit", 1, 7, "1.0")).unwrap();
+        assert_eq!(engine.apply(transaction("dispute", 1, 2, "")), Err(TxError::Rejected(RejectReason::NotDisputable)));
+        engine.apply(transaction("dispute", 1, 1, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("1.0"), to_amount("10.0"), to_amount("11.0"), false));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_handle_freeze_locks_every_balance_until_unlocked() {
+        let mut engine = Engine::new();
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(in_currency("deposit", 1, 2, "3.0", "EUR")).unwrap();
+
+        assert_eq!(engine.apply(admin("freeze", 2, 3, "fraud check")), Err(TxError::Rejected(RejectReason::UnknownAccount)));
+        assert_eq!(engine.apply(transaction("freeze", 1, 4, "")), Err(TxError::Rejected(RejectReason::MissingReason)));
+        assert_eq!(engine.apply(admin("freeze", 1, 5, "fraud check")), Ok(Outcome::Frozen));
+        assert!(engine.balances().all(|(_, _, account_info)| account_info.locked));
+        assert_eq!(engine.apply(transaction("deposit", 1, 6, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(admin("freeze", 1, 7, "again")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(admin("unlock", 1, 5, "replayed")), Err(TxError::Rejected(RejectReason::DuplicateTransaction)));
+
+        // one event per balance, nothing moves and nothing is booked
+        let events: Vec<(Option<Currency>, EventKind, bool)> = engine.ledger()[2..].iter().map(|event| (event.currency, event.kind, event.locked)).collect();
+        assert_eq!(events, vec![(None, EventKind::Freeze, true), ("EUR".parse().ok(), EventKind::Freeze, true)]);
+        assert_eq!(engine.check_books(), Ok(()));
+
+        engine.apply(admin("unlock", 1, 8, "cleared")).unwrap();
+        engine.apply(transaction("deposit", 1, 9, "1.0")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("11.0"), Amount::ZERO, to_amount("11.0"), false));
+        let folded = crate::ledger::fold_events(engine.ledger()).unwrap();
+        assert!(folded.values().all(|account_info| !account_info.locked));
+    }
+
+    #[test]
+    fn test_apply_locked_client_settles_open_disputes() {
+        let mut engine = Engine::new();
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("deposit", 1, 2, "5.0")).unwrap();
+        engine.apply(transaction("deposit", 1, 3, "3.0")).unwrap();
+        engine.apply(transaction("deposit", 1, 4, "1.0")).unwrap();
+        for trans_id in 1..=3 {
+            engine.apply(transaction("dispute", 1, trans_id, "")).unwrap();
+        }
+        engine.apply(transaction("chargeback", 1, 2, "")).unwrap();
+
+        // nothing new is accepted
+        assert_eq!(engine.apply(transaction("deposit", 1, 5, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(transaction("withdrawal", 1, 6, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(transaction("dispute", 1, 4, "")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+
+        // the disputes that were open settle and the client stays locked
+        assert_eq!(engine.apply(transaction("resolve", 1, 3, "")), Ok(Outcome::Resolved));
+        assert_eq!(balances(&engine, 1), (to_amount("4.0"), to_amount("10.0"), to_amount("14.0"), true));
+        assert!(engine.ledger().last().unwrap().locked);
+        assert_eq!(engine.apply(transaction("resolve", 1, 3, "")), Err(TxError::Rejected(RejectReason::NotDisputed)));
+        assert_eq!(engine.apply(transaction("chargeback", 1, 4, "")), Err(TxError::Rejected(RejectReason::NotDisputed)));
+        assert_eq!(engine.apply(transaction("chargeback", 1, 1, "")), Ok(Outcome::ChargedBack));
+        assert_eq!(balances(&engine, 1), (to_amount("4.0"), Amount::ZERO, to_amount("4.0"), true));
+
+        let folded = crate::ledger::fold_events(engine.ledger()).unwrap();
+        assert!(folded[&(1, None)].locked);
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_handle_transfer_settles_when_receiver_locked_since_the_dispute() {
+        let mut engine = engine_with_transfer();
+        engine.apply(transaction("dispute", 1, 3, "")).unwrap();
+        engine.apply(admin("freeze", 2, 10, "investigation")).unwrap();
+
+        engine.apply(transaction("resolve", 1, 3, "")).unwrap();
+        assert_eq!(balances(&engine, 2), (to_amount("5.0"), Amount::ZERO, to_amount("5.0"), true));
+        // a new dispute would hold funds at the locked receiver
+        assert_eq!(engine.apply(transaction("dispute", 1, 3, "")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+
+        let mut engine = engine_with_transfer();
+        engine.apply(transaction("dispute", 1, 3, "")).unwrap();
+        engine.apply(admin("freeze", 2, 10, "investigation")).unwrap();
+
+        engine.apply(transaction("chargeback", 1, 3, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("10.0"), Amount::ZERO, to_amount("10.0"), true));
+        assert_eq!(balances(&engine, 2), (to_amount("1.0"), Amount::ZERO, to_amount("1.0"), true));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    fn engine_accepting_when_locked(policy: &str) -> Engine {
+        Engine::with_config(EngineConfig { locked_accepts: policy.parse().unwrap(), ..Default::default() })
+    }
+
+    #[test]
+    fn test_apply_lock_policy_accepting_deposits() {
+        let mut engine = engine_accepting_when_locked("deposit");
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("deposit", 1, 2, "5.0")).unwrap();
+        engine.apply(transaction("deposit", 2, 3, "5.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("chargeback", 1, 1, "")).unwrap();
+
+        // deposits and transfers come in, the client stays locked in every balance
+        assert_eq!(engine.apply(transaction("deposit", 1, 4, "1.0")), Ok(Outcome::Deposited));
+        assert_eq!(engine.apply(in_currency("deposit", 1, 5, "2.0", "EUR")), Ok(Outcome::Deposited));
+        assert_eq!(engine.apply(transfer(2, 1, 6, "3.0")), Ok(Outcome::Transferred));
+        assert_eq!(balances(&engine, 1), (to_amount("4.0"), to_amount("5.0"), to_amount("9.0"), true));
+        assert!(engine.balances().filter(|(client, _, _)| *client == 1).all(|(_, _, account_info)| account_info.locked));
+
+        // nothing else does, not even the open dispute settling
+        assert_eq!(engine.apply(transaction("withdrawal", 1, 7, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(transfer(1, 2, 8, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(transaction("resolve", 1, 2, "")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+
+        let folded = crate::ledger::fold_events(engine.ledger()).unwrap();
+        assert!(folded[&(1, "EUR".parse().ok())].locked);
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_apply_lock_policy_accepting_nothing() {
+        let mut engine = engine_accepting_when_locked("none");
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("deposit", 1, 2, "5.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("chargeback", 1, 1, "")).unwrap();
+
+        assert_eq!(engine.apply(transaction("resolve", 1, 2, "")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(transaction("chargeback", 1, 2, "")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+
+        // admin rows are not part of the policy
+        engine.apply(admin("unlock", 1, 3, "settle the open dispute")).unwrap();
+        engine.apply(transaction("resolve", 1, 2, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("5.0"), Amount::ZERO, to_amount("5.0"), false));
+    }
+
+    #[test]
+    fn test_handle_transfer_lock_policy_checks_the_receiver() {
+        let mut engine = engine_accepting_when_locked("dispute");
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("deposit", 2, 2, "1.0")).unwrap();
+        engine.apply(transfer(1, 2, 3, "4.0")).unwrap();
+        engine.apply(admin("freeze", 2, 4, "investigation")).unwrap();
+
+        assert_eq!(engine.apply(transfer(1, 2, 5, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        engine.apply(transaction("dispute", 1, 3, "")).unwrap();
+        assert_eq!(balances(&engine, 2), (to_amount("1.0"), to_amount("4.0"), to_amount("5.0"), true));
+        assert_eq!(engine.apply(transaction("resolve", 1, 3, "")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(transaction("chargeback", 1, 3, "")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+    }
+
+    fn at(timestamp: u64, transaction: Transaction) -> Transaction {
+        Transaction { timestamp: Some(timestamp), ..transaction }
+    }
+
+    fn engine_with_deadlines(resolve_within: u64, on_deadline: DeadlineAction) -> Engine {
+        Engine::with_config(EngineConfig { resolve_within: Some(resolve_within), on_deadline, ..Default::default() })
+    }
+
+    #[test]
+    fn test_apply_dispute_window_closes() {
+        let mut engine = Engine::with_config(EngineConfig { dispute_window: Some(100), ..Default::default() });
+        engine.apply(at(1_000, transaction("deposit", 1, 1, "10.0"))).unwrap();
+        engine.apply(transaction("deposit", 1, 2, "5.0")).unwrap();
+        engine.apply(at(1_050, transaction("deposit", 1, 3, "1.0"))).unwrap();
+        assert_eq!(engine.transaction(1, 1).unwrap().timestamp, Some(1_000));
+
+        // the window is inclusive, a row rejected for its balance still moves the clock, settling could have changed the balance
+        engine.apply(at(1_100, transaction("dispute", 1, 1, ""))).unwrap();
+        engine.apply(transaction("resolve", 1, 1, "")).unwrap();
+        assert_eq!(engine.apply(at(1_101, transaction("withdrawal", 1, 4, "100.0"))), Err(TxError::Rejected(RejectReason::InsufficientFunds)));
+        assert_eq!(engine.clock(), Some(1_101));
+        assert_eq!(engine.apply(transaction("dispute", 1, 1, "")), Err(TxError::Rejected(RejectReason::DisputeWindowClosed)));
+
+        // a tx without a timestamp has no age, the clock never goes back
+        assert_eq!(engine.apply(transaction("dispute", 1, 2, "")), Ok(Outcome::Disputed));
+        assert_eq!(engine.apply(at(900, transaction("dispute", 1, 3, ""))), Ok(Outcome::Disputed));
+        assert_eq!(engine.clock(), Some(1_101));
+    }
+
+    #[test]
+    fn test_apply_rejected_rows_leave_the_clock_alone() {
+        let mut engine = engine_with_deadlines(60, DeadlineAction::Chargeback);
+        engine.apply(at(0, transaction("deposit", 1, 1, "10.0"))).unwrap();
+        engine.apply(at(0, transaction("deposit", 2, 2, "5.0"))).unwrap();
+        engine.apply(at(0, transaction("dispute", 1, 1, ""))).unwrap();
+        engine.apply(at(0, Transaction { reason: Some("kyc".to_string()), ..transaction("freeze", 2, 3, "") })).unwrap();
+
+        // a duplicate, a malformed row, a row of a locked client and a dispute in the wrong currency, all far in the future
+        let far = 1_000_000;
+        assert_eq!(engine.apply(at(far, transaction("deposit", 1, 1, "1.0"))), Err(TxError::Rejected(RejectReason::DuplicateTransaction)));
+        assert_eq!(engine.apply(at(far, transaction("withdrawal", 1, 4, "-1.0"))), Err(TxError::Rejected(RejectReason::InvalidAmount)));
+        assert_eq!(engine.apply(at(far, transaction("transfer", 1, 5, "1.0"))), Err(TxError::Rejected(RejectReason::InvalidDestination)));
+        assert_eq!(engine.apply(at(far, transaction("deposit", 2, 6, "1.0"))), Err(TxError::Rejected(RejectReason::AccountLocked)));
+        assert_eq!(engine.apply(at(far, transaction("bogus", 1, 7, "1.0"))), Err(TxError::Rejected(RejectReason::UnknownType)));
+        let other_currency = Transaction { currency: Some("EUR".parse().unwrap()), ..transaction("resolve", 1, 1, "") };
+        assert_eq!(engine.apply(at(far, other_currency)), Err(TxError::Rejected(RejectReason::CurrencyMismatch)));
+
+        assert_eq!(engine.clock(), Some(0));
+        assert_eq!(balances(&engine, 1), (Amount::ZERO, to_amount("10.0"), to_amount("10.0"), false));
+        assert_eq!(engine.deadlines.len(), 1);
+    }
+
+    #[test]
+    fn test_apply_resolve_deadline_resolves_open_disputes() {
+        let mut engine = engine_with_deadlines(3_600, DeadlineAction::Resolve);
+        engine.apply(at(0, transaction("deposit", 1, 1, "10.0"))).unwrap();
+        engine.apply(at(10, transaction("deposit", 1, 2, "5.0"))).unwrap();
+        engine.apply(at(100, transaction("dispute", 1, 1, ""))).unwrap();
+        engine.apply(at(200, transaction("dispute", 1, 2, ""))).unwrap();
+        assert_eq!(engine.transaction(1, 1).unwrap().disputes[0].deadline, Some(3_700));
+
+        // settled by hand before its deadline, nothing happens to it later
+        engine.apply(at(300, transaction("resolve", 1, 2, ""))).unwrap();
+        assert!(engine.transaction(1, 2).unwrap().disputes.is_empty());
+
+        // at its deadline it is still open, the clock has to pass it
+        engine.advance_clock(3_700);
+        assert!(engine.transaction(1, 1).unwrap().dispute);
+
+        let ledger_len = engine.ledger().len();
+        engine.apply(at(3_701, transaction("deposit", 2, 3, "1.0"))).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("15.0"), Amount::ZERO, to_amount("15.0"), false));
+        let status = engine.transaction(1, 1).unwrap();
+        assert!(!status.dispute && status.disputes.is_empty());
+
+        // the resolve comes before the row that moved the clock
+        let events = &engine.ledger()[ledger_len..];
+        assert_eq!((events[0].kind, events[0].reason.as_deref()), (EventKind::Resolve, Some("dispute deadline passed")));
+        assert_eq!((events[1].kind, events[1].reason.as_deref()), (EventKind::Deposit, None));
+        assert_eq!(engine.apply(transaction("resolve", 1, 1, "")), Err(TxError::Rejected(RejectReason::NotDisputed)));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_advance_clock_charges_back_expired_disputes() {
+        let mut engine = engine_with_deadlines(60, DeadlineAction::Chargeback);
+        engine.apply(at(0, transaction("deposit", 1, 1, "10.0"))).unwrap();
+        engine.apply(at(0, transaction("deposit", 1, 2, "5.0"))).unwrap();
+        engine.apply(at(0, transaction("dispute", 1, 1, ""))).unwrap();
+
+        engine.advance_clock(60);
+        assert!(engine.transaction(1, 1).unwrap().dispute);
+        engine.advance_clock(61);
+        assert_eq!(balances(&engine, 1), (to_amount("5.0"), Amount::ZERO, to_amount("5.0"), true));
+        assert!(engine.transaction(1, 1).unwrap().charged_back);
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_apply_partial_disputes_of_a_deposit() {
+        let mut engine = Engine::new();
+        engine.apply(transaction("deposit", 1, 1, "100.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "30.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "50.0")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("20.0"), to_amount("80.0"), to_amount("100.0"), false));
+        assert_eq!(engine.transaction(1, 1).unwrap().disputed, to_amount("80.0"));
+
+        assert_eq!(engine.apply(transaction("dispute", 1, 1, "30.0")), Err(TxError::Rejected(RejectReason::ExceedsUndisputed)));
+        assert_eq!(engine.apply(transaction("dispute", 1, 1, "")), Err(TxError::Rejected(RejectReason::AlreadyDisputed)));
+        assert_eq!(engine.apply(transaction("dispute", 1, 1, "-1.0")), Err(TxError::Rejected(RejectReason::InvalidAmount)));
+
+        // resolve and chargeback pick the dispute by its amount, or the oldest one
+        assert_eq!(engine.apply(transaction("resolve", 1, 1, "10.0")), Err(TxError::Rejected(RejectReason::NotDisputed)));
+        engine.apply(transaction("resolve", 1, 1, "50.0")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("70.0"), to_amount("30.0"), to_amount("100.0"), false));
+        engine.apply(transaction("chargeback", 1, 1, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("70.0"), Amount::ZERO, to_amount("70.0"), true));
+        let status = engine.transaction(1, 1).unwrap();
+        assert_eq!((status.disputed, status.charged_back_amount, status.charged_back), (Amount::ZERO, to_amount("30.0"), false));
+
+        // what was not charged back can still be disputed, once all of it is charged back the tx is settled
+        engine.apply(admin("unlock", 1, 2, "partial chargeback reviewed")).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (Amount::ZERO, to_amount("70.0"), to_amount("70.0"), false));
+        engine.apply(transaction("chargeback", 1, 1, "70.0")).unwrap();
+        assert!(engine.transaction(1, 1).unwrap().charged_back);
+        engine.apply(admin("unlock", 1, 3, "chargeback reviewed")).unwrap();
+        assert_eq!(engine.apply(transaction("dispute", 1, 1, "1.0")), Err(TxError::Rejected(RejectReason::NotDisputable)));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_apply_partial_disputes_of_a_withdrawal_with_fees() {
+        let mut engine = engine_with_fees(&["withdrawal=1.0", "chargeback=10%"]);
+        engine.apply(transaction("deposit", 1, 1, "20.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 2, "10.0")).unwrap();
+
+        // the fee is only paid back with a dispute of the whole withdrawal
+        engine.apply(transaction("dispute", 1, 2, "4.0")).unwrap();
+        engine.apply(transaction("resolve", 1, 2, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("9.0"), Amount::ZERO, to_amount("9.0"), false));
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("resolve", 1, 2, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("10.0"), Amount::ZERO, to_amount("10.0"), false));
+
+        // the chargeback fee is on the part charged back
+        engine.apply(transaction("dispute", 1, 2, "4.0")).unwrap();
+        engine.apply(transaction("chargeback", 1, 2, "4.0")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("13.6"), Amount::ZERO, to_amount("13.6"), true));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_apply_partial_resolves_of_a_withdrawal_pay_the_fee_back_once() {
+        let mut engine = engine_with_fees(&["withdrawal=1.0"]);
+        engine.apply(transaction("deposit", 1, 1, "20.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 2, "10.0")).unwrap();
+
+        // the fee comes back with the resolve that makes the resolved parts add up to the whole withdrawal
+        engine.apply(transaction("dispute", 1, 2, "4.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "6.0")).unwrap();
+        engine.apply(transaction("resolve", 1, 2, "6.0")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("9.0"), to_amount("4.0"), to_amount("13.0"), false));
+        engine.apply(transaction("resolve", 1, 2, "4.0")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("10.0"), Amount::ZERO, to_amount("10.0"), false));
+        assert_eq!(engine.transaction(1, 2).unwrap().resolved_amount, to_amount("10.0"));
+
+        // only once
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("resolve", 1, 2, "")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("10.0"), Amount::ZERO, to_amount("10.0"), false));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_handle_transfer_partial_dispute_and_chargeback() {
+        let mut engine = engine_with_transfer();
+        engine.apply(transaction("dispute", 1, 3, "1.5")).unwrap();
+        engine.apply(transaction("dispute", 1, 3, "1.0")).unwrap();
+        assert_eq!(balances(&engine, 2), (to_amount("2.5"), to_amount("2.5"), to_amount("5.0"), false));
+
+        engine.apply(transaction("chargeback", 1, 3, "1.0")).unwrap();
+        assert_eq!(balances(&engine, 1), (to_amount("7.0"), Amount::ZERO, to_amount("7.0"), true));
+        assert_eq!(balances(&engine, 2), (to_amount("2.5"), to_amount("1.5"), to_amount("4.0"), false));
+        engine.apply(transaction("resolve", 1, 3, "")).unwrap();
+        assert_eq!(balances(&engine, 2), (to_amount("4.0"), Amount::ZERO, to_amount("4.0"), false));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_apply_partial_disputes_have_their_own_deadlines() {
+        let mut engine = engine_with_deadlines(100, DeadlineAction::Resolve);
+        engine.apply(at(0, transaction("deposit", 1, 1, "10.0"))).unwrap();
+        engine.apply(at(0, transaction("dispute", 1, 1, "2.0"))).unwrap();
+        engine.apply(at(0, transaction("dispute", 1, 1, "3.0"))).unwrap();
+        engine.apply(at(50, transaction("dispute", 1, 1, "4.0"))).unwrap();
+
+        engine.advance_clock(101);
+        assert_eq!(balances(&engine, 1), (to_amount("6.0"), to_amount("4.0"), to_amount("10.0"), false));
+        assert_eq!(engine.transaction(1, 1).unwrap().disputes, vec![Dispute { id: 3, amount: to_amount("4.0"), deadline: Some(150) }]);
+
+        engine.apply(transaction("resolve", 1, 1, "4.0")).unwrap();
+        assert!(engine.deadlines.is_empty());
+    }
+
+    #[test]
+    fn test_apply_settles_equal_disputes_by_their_id() {
+        let mut engine = engine_with_deadlines(100, DeadlineAction::Chargeback);
+        engine.apply(at(0, transaction("deposit", 1, 1, "10.0"))).unwrap();
+        engine.apply(at(0, transaction("dispute", 1, 1, "2.0"))).unwrap();
+        engine.apply(at(50, transaction("dispute", 1, 1, "2.0"))).unwrap();
+
+        // the amount alone would settle the oldest one, the id picks the other
+        let by_id = |trans_type: &str, id: u32, amount: &str| Transaction { dispute: Some(id), ..transaction(trans_type, 1, 1, amount) };
+        assert_eq!(engine.apply(by_id("resolve", 2, "3.0")), Err(TxError::Rejected(RejectReason::NotDisputed)));
+        assert_eq!(engine.apply(by_id("resolve", 3, "")), Err(TxError::Rejected(RejectReason::NotDisputed)));
+        assert_eq!(engine.apply(by_id("resolve", 2, "2.0")), Ok(Outcome::Resolved));
+        assert_eq!(engine.apply(by_id("chargeback", 2, "")), Err(TxError::Rejected(RejectReason::NotDisputed)));
+        assert_eq!(engine.transaction(1, 1).unwrap().disputes, vec![Dispute { id: 1, amount: to_amount("2.0"), deadline: Some(100) }]);
+
+        // the next dispute gets a new id, the one that is still open keeps its deadline
+        engine.apply(at(60, transaction("dispute", 1, 1, "2.0"))).unwrap();
+        engine.apply(by_id("chargeback", 3, "")).unwrap();
+        engine.advance_clock(101);
+        assert_eq!(balances(&engine, 1), (to_amount("6.0"), Amount::ZERO, to_amount("6.0"), true));
+        assert_eq!(engine.transaction(1, 1).unwrap().charged_back_amount, to_amount("4.0"));
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_apply_rejects_duplicate_transaction_ids() {
+        let mut engine = Engine::new();
+
+        engine.apply(transaction("deposit", 1, 1, "5.0")).unwrap();
+
+        // same client, same id, the first deposit must not be overwritten
+        assert_eq!(engine.apply(transaction("deposit", 1, 1, "9.0")), Err(TxError::Rejected(RejectReason::DuplicateTransaction)));
+        assert_eq!(engine.apply(transaction("withdrawal", 1, 1, "1.0")), Err(TxError::Rejected(RejectReason::DuplicateTransaction)));
+        assert_eq!(engine.transaction(1, 1).unwrap().amount, to_amount("5.0"));
+
+        // another client cannot reuse the id either
+        assert_eq!(engine.apply(transaction("deposit", 2, 1, "1.0")), Err(TxError::Rejected(RejectReason::DuplicateTransaction)));
+        assert!(engine.account(2).is_none());
+        assert_eq!(engine.account(1).unwrap().total, to_amount("5.0"));
+    }
+
+    #[test]
+    fn test_apply_rejected_transaction_still_claims_its_id() {
+        let mut engine = Engine::new();
+
+        engine.apply(transaction("deposit", 1, 1, "1.0")).unwrap();
+        assert_eq!(engine.apply(transaction("withdrawal", 1, 2, "5.0")), Err(TxError::Rejected(RejectReason::InsufficientFunds)));
+        engine.apply(transaction("deposit", 1, 3, "10.0")).unwrap();
+
+        // replaying the rejected withdrawal once funds are there still fails
+        assert_eq!(engine.apply(transaction("withdrawal", 1, 2, "5.0")), Err(TxError::Rejected(RejectReason::DuplicateTransaction)));
+        assert_eq!(engine.account(1).unwrap().available, to_amount("11.0"));
+    }
+
+    #[test]
+    fn test_apply_rejects_dispute_from_another_client() {
+        let mut engine = Engine::new();
+
+        engine.apply(transaction("deposit", 1, 1, "1.0")).unwrap();
+        engine.apply(transaction("deposit", 2, 2, "1.0")).unwrap();
+
+        assert_eq!(engine.apply(transaction("dispute", 2, 1, "")), Err(TxError::Rejected(RejectReason::ClientMismatch)));
+        assert_eq!(engine.apply(transaction("dispute", 3, 1, "")), Err(TxError::Rejected(RejectReason::ClientMismatch)));
+        assert_eq!(engine.apply(transaction("dispute", 2, 9, "")), Err(TxError::Rejected(RejectReason::UnknownTransaction)));
+        assert_eq!(engine.account(1).unwrap().held, Amount::ZERO);
+    }
+
+    #[test]
+    fn test_account_lookup_and_iteration() {
+        let mut engine = Engine::new();
+
+        engine.apply(transaction("deposit", 1, 1, "1.5")).unwrap();
+        engine.apply(transaction("deposit", 2, 2, "2.5")).unwrap();
+
+        assert_eq!(engine.account(1).unwrap().available, to_amount("1.5"));
+        assert_eq!(engine.transaction(2, 2).unwrap().amount, to_amount("2.5"));
+        assert!(engine.transaction(1, 2).is_none());
+        assert_eq!(engine.find_transaction(2).unwrap().0, 2);
+        assert!(engine.find_transaction(3).is_none());
+
+        let mut clients: Vec<u16> = engine.accounts().map(|(client, _)| client).collect();
+        clients.sort();
+        assert_eq!(clients, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_handle_record_two_clients_three_deposits_two_withdrawals_one_dispute(){
+        let client1deposit1 = Transaction{
+            trans_type: "Deposit".to_string(),
+            client: 1,
+            id: 1,
+            amount: Some(to_amount("2.0")),
+            ..Default::default()
+        };
+
+        let client1withdrawal1 = Transaction{
+            trans_type: "Withdrawal".to_string(),
+            client: 1,
+            id: 2,
+            amount: Some(to_amount("1.0")),
+            ..Default::default()
+        };
+
+        let client2deposit2 = Transaction{
+            trans_type: "Deposit".to_string(),
+            client: 2,
+            id: 3,
+            amount: Some(to_amount("3.0")),
+            ..Default::default()
+        };
+
+        let client2withdrawal2 = Transaction{
+            trans_type: "Withdrawal".to_string(),
+            client: 2,
+            id: 4,
+            amount: Some(to_amount("1.75")),
+            ..Default::default()
+        };
+
+        let client2deposit3 = Transaction{
+            trans_type: "Deposit".to_string(),
+            client: 2,
+            id: 5,
+            amount: Some(to_amount("3.0")),
+            ..Default::default()
+        };
+
+        let client2dispute1 = Transaction{
+            trans_type: "Dispute".to_string(),
+            client: 2,
+            id: 5,
+            amount: None,
+            ..Default::default()
+        };
+
+        let mut engine = Engine::new();
+
+        engine.apply(client1deposit1).unwrap();
+        engine.apply(client1withdrawal1).unwrap();
+        engine.apply(client2deposit2).unwrap();
+        engine.apply(client2withdrawal2).unwrap();
+        engine.apply(client2deposit3).unwrap();
+        engine.apply(client2dispute1).unwrap();
+
+        let expected_client1_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("0.0"),
+            total: to_amount("1.0"),
+            locked: false
+        };
+
+        let expected_client2_account_info = AccountInfo{
+            available: to_amount("1.25"),
+            held: to_amount("3.0"),
+            total: to_amount("4.25"),
+            locked: false
+        };
+
+        let client1_account = engine.accounts.get(&1).unwrap();
+        assert_eq!(client1_account.total, expected_client1_account_info.total);
+        assert_eq!(client1_account.held, expected_client1_account_info.held);
+        assert_eq!(client1_account.available, expected_client1_account_info.available);
+        assert_eq!(client1_account.locked, expected_client1_account_info.locked);
+
+        let client2_account = engine.accounts.get(&2).unwrap();
+        assert_eq!(client2_account.total, expected_client2_account_info.total);
+        assert_eq!(client2_account.held, expected_client2_account_info.held);
+        assert_eq!(client2_account.available, expected_client2_account_info.available);
+        assert_eq!(client2_account.locked, expected_client2_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_record_three_deposits_two_withdrawals_one_dispute_one_resolve(){
+        let client1deposit1 = Transaction{
+            trans_type: "Deposit".to_string(),
+            client: 1,
+            id: 1,
+            amount: Some(to_amount("2.0")),
+            ..Default::default()
+        };
+
+        let client1withdrawal1 = Transaction{
+            trans_type: "Withdrawal".to_string(),
+            client: 1,
+            id: 2,
+            amount: Some(to_amount("1.0")),
+            ..Default::default()
+        };
+
+        let client2deposit2 = Transaction{
+            trans_type: "Deposit".to_string(),
+            client: 2,
+            id: 3,
+            amount: Some(to_amount("3.0")),
+            ..Default::default()
+        };
+
+        let client2withdrawal2 = Transaction{
+            trans_type: "Withdrawal".to_string(),
+            client: 2,
+            id: 4,
+            amount: Some(to_amount("1.75")),
+            ..Default::default()
+        };
+
+        let client2deposit3 = Transaction{
+            trans_type: "Deposit".to_string(),
+            client: 2,
+            id: 5,
+            amount: Some(to_amount("3.0")),
+            ..Default::default()
+        };
+
+        let client2dispute1 = Transaction{
+            trans_type: "Dispute".to_string(),
+            client: 2,
+            id: 5,
+            amount: None,
+            ..Default::default()
+        };
+
+        let client2resolve1 = Transaction{
+            trans_type: "Resolve".to_string(),
+            client: 2,
+            id: 5,
+            amount: None,
+            ..Default::default()
+        };
+
+        let mut engine = Engine::new();
+
+        engine.apply(client1deposit1).unwrap();
+        engine.apply(client1withdrawal1).unwrap();
+        engine.apply(client2deposit2).unwrap();
+        engine.apply(client2withdrawal2).unwrap();
+        engine.apply(client2deposit3).unwrap();
+        engine.apply(client2dispute1).unwrap();
+        engine.apply(client2resolve1).unwrap();
+
+        let expected_client1_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("0.0"),
+            total: to_amount("1.0"),
+            locked: false
+        };
+
+        let expected_client2_account_info = AccountInfo{
+            available: to_amount("4.25"),
+            held: to_amount("0.0"),
+            total: to_amount("4.25"),
+            locked: false
+        };
+
+        let client1_account = engine.accounts.get(&1).unwrap();
+        assert_eq!(client1_account.total, expected_client1_account_info.total);
+        assert_eq!(client1_account.held, expected_client1_account_info.held);
+        assert_eq!(client1_account.available, expected_client1_account_info.available);
+        assert_eq!(client1_account.locked, expected_client1_account_info.locked);
+
+        let client2_account = engine.accounts.get(&2).unwrap();
+        assert_eq!(client2_account.total, expected_client2_account_info.total);
+        assert_eq!(client2_account.held, expected_client2_account_info.held);
+        assert_eq!(client2_account.available, expected_client2_account_info.available);
+        assert_eq!(client2_account.locked, expected_client2_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_record_three_deposits_two_withdrawals_one_dispute_one_chargeback(){
+        let client1deposit1 = Transaction{
+            trans_type: "Deposit".to_string(),
+            client: 1,
+            id: 1,
+            amount: Some(to_amount("2.0")),
+            ..Default::default()
+        };
+
+        let client1withdrawal1 = Transaction{
+            trans_type: "Withdrawal".to_string(),
+            client: 1,
+            id: 2,
+            amount: Some(to_amount("1.0")),
+            ..Default::default()
+        };
+
+        let client2deposit2 = Transaction{
+            trans_type: "Deposit".to_string(),
+            client: 2,
+            id: 3,
+            amount: Some(to_amount("3.0")),
+            ..Default::default()
+        };
+
+        let client2withdrawal2 = Transaction{
+            trans_type: "Withdrawal".to_string(),
+            client: 2,
+            id: 4,
+            amount: Some(to_amount("1.75")),
+            ..Default::default()
+        };
+
+        let client2deposit3 = Transaction{
+            trans_type: "Deposit".to_string(),
+            client: 2,
+            id: 5,
+            amount: Some(to_amount("3.0")),
+            ..Default::default()
+        };
+
+        let client2dispute1 = Transaction{
+            trans_type: "Dispute".to_string(),
+            client: 2,
+            id: 5,
+            amount: None,
+            ..Default::default()
+        };
+
+        let client2resolve1 = Transaction{
+            trans_type: "Chargeback".to_string(),
+            client: 2,
+            id: 5,
+            amount: None,
+            ..Default::default()
+        };
+
+        let mut engine = Engine::new();
+
+        engine.apply(client1deposit1).unwrap();
+        engine.apply(client1withdrawal1).unwrap();
+        engine.apply(client2deposit2).unwrap();
+        engine.apply(client2withdrawal2).unwrap();
+        engine.apply(client2deposit3).unwrap();
+        engine.apply(client2dispute1).unwrap();
+        engine.apply(client2resolve1).unwrap();
+
+        let expected_client1_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("0.0"),
+            total: to_amount("1.0"),
+            locked: false
+        };
+
+        let expected_client2_account_info = AccountInfo{
+            available: to_amount("1.25"),
+            held: to_amount("0.0"),
+            total: to_amount("1.25"),
+            locked: true
+        };
+
+        let client1_account = engine.accounts.get(&1).unwrap();
+        assert_eq!(client1_account.total, expected_client1_account_info.total);
+        assert_eq!(client1_account.held, expected_client1_account_info.held);
+        assert_eq!(client1_account.available, expected_client1_account_info.available);
+        assert_eq!(client1_account.locked, expected_client1_account_info.locked);
+
+        let client2_account = engine.accounts.get(&2).unwrap();
+        assert_eq!(client2_account.total, expected_client2_account_info.total);
+        assert_eq!(client2_account.held, expected_client2_account_info.held);
+        assert_eq!(client2_account.available, expected_client2_account_info.available);
+        assert_eq!(client2_account.locked, expected_client2_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_chargeback_with_deposit_disputed() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let trans_id2: u32 = 2;
+        let amount2: Amount = to_amount("2.0");
+
+        let mut engine = Engine::new();
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("0.0"),
+            total: to_amount("1.0"),
+            locked: true
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+        engine.handle_deposit(amount2, None, &client, trans_id2).unwrap();
+        engine.handle_dispute(&client, &trans_id2, None).unwrap();
+        engine.handle_chargeback(&client, &trans_id2, None, None).unwrap();
+
+        // client transactions are kept, the charged back one is settled
+        let trans_status = engine.transaction_status.get(&client).unwrap().get(&trans_id2).unwrap();
+        assert!(trans_status.charged_back);
+        assert!(!trans_status.dispute);
+
+        // assert account numbers
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_chargeback_with_chargeback_duplicated() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let trans_id2: u32 = 2;
+        let amount2: Amount = to_amount("2.0");
+
+        let mut engine = Engine::new();
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("0.0"),
+            total: to_amount("1.0"),
+            locked: true
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+        engine.handle_deposit(amount2, None, &client, trans_id2).unwrap();
+        engine.handle_dispute(&client, &trans_id2, None).unwrap();
+        engine.handle_chargeback(&client, &trans_id2, None, None).unwrap();
+        assert_eq!(engine.handle_chargeback(&client, &trans_id2, None, None), Err(RejectReason::NotDisputed));
+
+        // client transactions are kept, the charged back one cannot be disputed again
+        assert_eq!(engine.handle_dispute(&client, &trans_id2, None), Err(RejectReason::NotDisputable));
+        assert!(engine.transaction_status.get(&client).unwrap().contains_key(&trans_id));
+
+        // assert account numbers
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_chargeback_with_nonexistent_transaction_id() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let trans_id2: u32 = 2;
+        let amount2: Amount = to_amount("2.0");
+
+        let bad_id: u32 = 3;
+
+        let mut engine = Engine::new();
+
+        // funds stays held, no reversal
+        let expected_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("2.0"),
+            total: to_amount("3.0"),
+            locked: false
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+        engine.handle_deposit(amount2, None, &client, trans_id2).unwrap();
+        engine.handle_dispute(&client, &trans_id2, None).unwrap();
+        assert_eq!(engine.handle_chargeback(&client, &bad_id, None, None), Err(RejectReason::UnknownTransaction));
+
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    fn account(available: &str, held: &str, total: &str, locked: bool) -> AccountInfo {
+        AccountInfo {
+            available: to_amount(available),
+            held: to_amount(held),
+            total: to_amount(total),
+            locked,
+        }
+    }
+
+    fn engine_with_withdrawal(config: EngineConfig) -> Engine {
+        // client 1 deposits 5.0 as tx 1 and withdraws 2.0 as tx 2
+        let mut engine = Engine::with_config(config);
+        engine.handle_deposit(to_amount("5.0"), None, &1, 1).unwrap();
+        engine.handle_withdrawal(to_amount("2.0"), None, &1, 2).unwrap();
+        engine
+    }
+
+    #[test]
+    fn test_handle_dispute_with_withdrawal_provisional_credit() {
+        let mut engine = engine_with_withdrawal(EngineConfig::default());
+
+        engine.handle_dispute(&1, &2, None).unwrap();
+
+        // withdrawn amount is credited to held, available is untouched
+        assert_eq!(engine.accounts.get(&1).unwrap(), &account("3.0", "2.0", "5.0", false));
+        assert!(engine.transaction_status.get(&1).unwrap().get(&2).unwrap().dispute);
+    }
+
+    #[test]
+    fn test_handle_dispute_with_withdrawal_duplicate_dispute() {
+        let mut engine = engine_with_withdrawal(EngineConfig::default());
+
+        engine.handle_dispute(&1, &2, None).unwrap();
+        assert_eq!(engine.handle_dispute(&1, &2, None), Err(RejectReason::AlreadyDisputed));
+
+        assert_eq!(engine.accounts.get(&1).unwrap(), &account("3.0", "2.0", "5.0", false));
+    }
+
+    #[test]
+    fn test_handle_dispute_with_withdrawal_disputes_rejected() {
+        let config = EngineConfig { withdrawal_disputes: WithdrawalDisputes::Reject, ..Default::default() };
+        let mut engine = engine_with_withdrawal(config);
+
+        assert_eq!(engine.handle_dispute(&1, &2, None), Err(RejectReason::NotDisputable));
+        assert_eq!(engine.handle_resolve(&1, &2, None, None), Err(RejectReason::NotDisputed));
+        assert_eq!(engine.handle_chargeback(&1, &2, None, None), Err(RejectReason::NotDisputed));
+
+        // deposits can still be disputed
+        engine.handle_dispute(&1, &1, None).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &account("-2.0", "5.0", "3.0", false));
+    }
+
+    #[test]
+    fn test_handle_resolve_with_withdrawal_disputed() {
+        let mut engine = engine_with_withdrawal(EngineConfig::default());
+
+        engine.handle_dispute(&1, &2, None).unwrap();
+        engine.handle_resolve(&1, &2, None, None).unwrap();
+
+        // withdrawal stands, the provisional credit is gone
+        assert_eq!(engine.accounts.get(&1).unwrap(), &account("3.0", "0.0", "3.0", false));
+        assert!(!engine.transaction_status.get(&1).unwrap().get(&2).unwrap().dispute);
+
+        // and it can be disputed again
+        engine.handle_dispute(&1, &2, None).unwrap();
+        assert_eq!(engine.accounts.get(&1).unwrap(), &account("3.0", "2.0", "5.0", false));
+    }
+
+    #[test]
+    fn test_handle_resolve_with_withdrawal_not_disputed() {
+        let mut engine = engine_with_withdrawal(EngineConfig::default());
+
+        assert_eq!(engine.handle_resolve(&1, &2, None, None), Err(RejectReason::NotDisputed));
+        assert_eq!(engine.accounts.get(&1).unwrap(), &account("3.0", "0.0", "3.0", false));
+    }
+
+    #[test]
+    fn test_handle_chargeback_with_withdrawal_disputed() {
+        let mut engine = engine_with_withdrawal(EngineConfig::default());
+
+        engine.handle_dispute(&1, &2, None).unwrap();
+        engine.handle_chargeback(&1, &2, None, None).unwrap();
+
+        // withdrawal reversed, the funds are back with the client and the account is locked
+        assert_eq!(engine.accounts.get(&1).unwrap(), &account("5.0", "0.0", "5.0", true));
+        assert!(engine.transaction_status.get(&1).unwrap().get(&2).unwrap().charged_back);
+    }
+
+    #[test]
+    fn test_handle_chargeback_with_withdrawal_not_disputed() {
+        let mut engine = engine_with_withdrawal(EngineConfig::default());
+
+        assert_eq!(engine.handle_chargeback(&1, &2, None, None), Err(RejectReason::NotDisputed));
+        assert_eq!(engine.accounts.get(&1).unwrap(), &account("3.0", "0.0", "3.0", false));
+    }
+
+    #[test]
+    fn test_apply_dispute_resolve_chargeback_with_withdrawal() {
+        let mut engine = Engine::new();
+
+        engine.apply(transaction("deposit", 1, 1, "5.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 2, "2.0")).unwrap();
+        assert_eq!(engine.apply(transaction("dispute", 1, 2, "")), Ok(Outcome::Disputed));
+        assert_eq!(engine.apply(transaction("resolve", 1, 2, "")), Ok(Outcome::Resolved));
+        assert_eq!(engine.apply(transaction("dispute", 1, 2, "")), Ok(Outcome::Disputed));
+        assert_eq!(engine.apply(transaction("chargeback", 1, 2, "")), Ok(Outcome::ChargedBack));
+
+        assert_eq!(engine.account(1).unwrap(), &account("5.0", "0.0", "5.0", true));
+    }
+
+    #[test]
+    fn test_handle_chargeback_without_dispute() {
+        let client: u16 = 1;
+
+        let mut engine = Engine::new();
+
+        engine.handle_deposit(to_amount("2.0"), None, &client, 1).unwrap();
+        assert_eq!(engine.handle_chargeback(&client, &1, None, None), Err(RejectReason::NotDisputed));
+        assert!(!engine.accounts.get(&client).unwrap().locked);
+    }
+
+    #[test]
+    fn test_handle_resolve_with_deposit_disputed() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let trans_id2: u32 = 2;
+        let amount2: Amount = to_amount("2.0");
+
+        let mut engine = Engine::new();
+
+        // dispute resolved, expect false dispute
+        let expected_trans_status = TransactionStatus {
+            amount: to_amount("2.0"),
+            deposit: true,
+            dispute: false,
+            ..Default::default()
+        };
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("3.0"),
+            held: to_amount("0.0"),
+            total: to_amount("3.0"),
+            locked: false
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+        engine.handle_deposit(amount2, None, &client, trans_id2).unwrap();
+        engine.handle_dispute(&client, &trans_id2, None).unwrap();
+        engine.handle_resolve(&client, &trans_id2, None, None).unwrap();
+
+        // assert second transaction exists
+        let actual_trans_status = engine.transaction_status.get(&client).unwrap().get(&trans_id2).unwrap();
+        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
+        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
+        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
+
+        // assert account numbers
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_resolve_with_resolve_duplicated() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let trans_id2: u32 = 2;
+        let amount2: Amount = to_amount("2.0");
+
+        let mut engine = Engine::new();
+
+        // dispute resolved, expect false dispute
+        let expected_trans_status = TransactionStatus {
+            amount: to_amount("2.0"),
+            deposit: true,
+            dispute: false,
+            ..Default::default()
+        };
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("3.0"),
+            held: to_amount("0.0"),
+            total: to_amount("3.0"),
+            locked: false
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+        engine.handle_deposit(amount2, None, &client, trans_id2).unwrap();
+        engine.handle_dispute(&client, &trans_id2, None).unwrap();
+        engine.handle_resolve(&client, &trans_id2, None, None).unwrap();
+        assert_eq!(engine.handle_resolve(&client, &trans_id2, None, None), Err(RejectReason::NotDisputed));
+
+        // assert second transaction exists
+        let actual_trans_status = engine.transaction_status.get(&client).unwrap().get(&trans_id2).unwrap();
+        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
+        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
+        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
+
+        // assert account numbers
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_resolve_with_nonexistent_transaction_id() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let trans_id2: u32 = 2;
+        let amount2: Amount = to_amount("2.0");
+
+        let bad_id: u32 = 3;
+
+        let mut engine = Engine::new();
+
+        // funds stays held
+        let expected_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("2.0"),
+            total: to_amount("3.0"),
+            locked: false
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+        engine.handle_deposit(amount2, None, &client, trans_id2).unwrap();
+        engine.handle_dispute(&client, &trans_id2, None).unwrap();
+        assert_eq!(engine.handle_resolve(&client, &bad_id, None, None), Err(RejectReason::UnknownTransaction));
+
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_dispute_with_deposits() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let trans_id2: u32 = 2;
+        let amount2: Amount = to_amount("2.0");
+
+        let mut engine = Engine::new();
+
+
+        let expected_trans_status = TransactionStatus {
+            amount: to_amount("2.0"),
+            deposit: true,
+            dispute: true,
+            ..Default::default()
+        };
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("2.0"),
+            total: to_amount("3.0"),
+            locked: false
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+        engine.handle_deposit(amount2, None, &client, trans_id2).unwrap();
+        engine.handle_dispute(&client, &trans_id2, None).unwrap();
+
+        // assert second transaction exists
+        let actual_trans_status = engine.transaction_status.get(&client).unwrap().get(&trans_id2).unwrap();
+        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
+        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
+        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
+
+        // assert account numbers
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_dispute_with_duplicate_dispute() {
+        // should not move to held funds more than once
+
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let trans_id2: u32 = 2;
+        let amount2: Amount = to_amount("2.0");
+
+        let mut engine = Engine::new();
+
+
+        let expected_trans_status = TransactionStatus {
+            amount: to_amount("2.0"),
+            deposit: true,
+            dispute: true,
+            ..Default::default()
+        };
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("2.0"),
+            total: to_amount("3.0"),
+            locked: false
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+        engine.handle_deposit(amount2, None, &client, trans_id2).unwrap();
+        engine.handle_dispute(&client, &trans_id2, None).unwrap();
+        assert_eq!(engine.handle_dispute(&client, &trans_id2, None), Err(RejectReason::AlreadyDisputed));
+
+        // assert second transaction exists
+        let actual_trans_status = engine.transaction_status.get(&client).unwrap().get(&trans_id2).unwrap();
+        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
+        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
+        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
+
+        // assert account numbers
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_dispute_with_nonexistent_transaction_id() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let trans_id2: u32 = 2;
+        let amount2: Amount = to_amount("2.0");
+
+        let bad_id: u32 = 3;
+
+        let mut engine = Engine::new();
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("3.0"),
+            held: to_amount("0.0"),
+            total: to_amount("3.0"),
+            locked: false
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+        engine.handle_deposit(amount2, None, &client, trans_id2).unwrap();
+        assert_eq!(engine.handle_dispute(&client, &bad_id, None), Err(RejectReason::UnknownTransaction));
+
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_withdrawal_with_new_account() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let mut engine = Engine::new();
+
+        assert_eq!(engine.handle_withdrawal(amount, None, &client, trans_id), Err(RejectReason::UnknownAccount));
+
+        // assert transaction exists
+        let trans_status = engine.transaction_status.get(&client);
+        assert!(trans_status.is_none());
+
+        // assert account numbers
+        let account_info = engine.accounts.get(&client);
+        assert!(account_info.is_none())
+    }
+
+    #[test]
+    fn test_handle_withdrawal_with_existing_account() {
+        let client: u16 = 1;
+        let deposit_trans_id: u32 = 1;
+        let withdrawal_trans_id: u32 = 2;
+        let amount: Amount = to_amount("1.0");
+
+        let mut engine = Engine::new();
+
+        let expected_withdrawal_trans_status = TransactionStatus {
+            amount: to_amount("1.0"),
+            deposit: false,
+            dispute: false,
+            ..Default::default()
+        };
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("0.0"),
+            held: to_amount("0.0"),
+            total: to_amount("0.0"),
+            locked: false
+        };
+        engine.handle_deposit(amount, None, &client, deposit_trans_id).unwrap();
+        engine.handle_withdrawal(amount, None, &client, withdrawal_trans_id).unwrap();
+
+        // assert transaction exists
+        let actual_trans_status = engine.transaction_status.get(&client).unwrap().get(&withdrawal_trans_id).unwrap();
+        assert_eq!(expected_withdrawal_trans_status.amount, actual_trans_status.amount);
+        assert_eq!(expected_withdrawal_trans_status.deposit, actual_trans_status.deposit);
+        assert_eq!(expected_withdrawal_trans_status.dispute, actual_trans_status.dispute);
+
+        // assert account numbers
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_withdrawal_with_existing_account_insufficient_funds() {
+        let client: u16 = 1;
+        let deposit_trans_id: u32 = 1;
+        let withdrawal_trans_id: u32 = 2;
+        let withdrawal_amount: Amount = to_amount("2.0");
+        let amount: Amount = to_amount("1.0");
+
+        let mut engine = Engine::new();
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("0.0"),
+            total: to_amount("1.0"),
+            locked: false
+        };
+
+        engine.handle_deposit(amount, None, &client, deposit_trans_id).unwrap();
+        assert_eq!(engine.handle_withdrawal(withdrawal_amount, None, &client, withdrawal_trans_id), Err(RejectReason::InsufficientFunds));
+
+        // assert transaction exists
+        let actual_trans_status = engine.transaction_status.get(&client).unwrap().get(&withdrawal_trans_id);
+        assert!(actual_trans_status.is_none());
+
+        // assert account numbers
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_deposit_with_new_account() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let mut engine = Engine::new();
+
+        let expected_trans_status = TransactionStatus {
+            amount: to_amount("1.0"),
+            deposit: true,
+            dispute: false,
+            ..Default::default()
+        };
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("1.0"),
+            held: to_amount("0.0"),
+            total: to_amount("1.0"),
+            locked: false
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+
+        // assert transaction exists
+        let actual_trans_status = engine.transaction_status.get(&client).unwrap().get(&trans_id).unwrap();
+        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
+        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
+        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
+
+        // assert account numbers
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_deposit_with_existing_account() {
+        let client: u16 = 1;
+        let trans_id: u32 = 1;
+        let amount: Amount = to_amount("1.0");
+
+        let trans_id2: u32 = 2;
+        let amount2: Amount = to_amount("2.0");
+
+        let mut engine = Engine::new();
+
+
+        let expected_trans_status = TransactionStatus {
+            amount: to_amount("2.0"),
+            deposit: true,
+            dispute: false,
+            ..Default::default()
+        };
+
+        let expected_account_info = AccountInfo{
+            available: to_amount("3.0"),
+            held: to_amount("0.0"),
+            total: to_amount("3.0"),
+            locked: false
+        };
+
+        engine.handle_deposit(amount, None, &client, trans_id).unwrap();
+        engine.handle_deposit(amount2, None, &client, trans_id2).unwrap();
+
+        // assert second transaction exists
+        let actual_trans_status = engine.transaction_status.get(&client).unwrap().get(&trans_id2).unwrap();
+        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
+        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
+        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
+
+        // assert account numbers
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(expected_account_info.available, actual_account_info.available);
+        assert_eq!(expected_account_info.held, actual_account_info.held);
+        assert_eq!(expected_account_info.total, actual_account_info.total);
+        assert_eq!(expected_account_info.locked, actual_account_info.locked);
+    }
+
+    #[test]
+    fn test_handle_withdrawal_with_fractional_deposits_is_exact() {
+        // 0.1 + 0.2 would leave 0.30000000000000004 with floats, so withdrawing 0.3 must empty the account
+        let client: u16 = 1;
+
+        let mut engine = Engine::new();
+
+        engine.handle_deposit(to_amount("0.1"), None, &client, 1).unwrap();
+        engine.handle_deposit(to_amount("0.2"), None, &client, 2).unwrap();
+        engine.handle_withdrawal(to_amount("0.3"), None, &client, 3).unwrap();
+
+        let actual_account_info = engine.accounts.get(&client).unwrap();
+        assert_eq!(actual_account_info.available, Amount::ZERO);
+        assert_eq!(actual_account_info.total, Amount::ZERO);
+        assert!(engine.transaction_status.get(&client).unwrap().contains_key(&3));
+    }
+
+    #[test]
+    fn test_does_transaction_exist_without_dispute_has_dispute() {
+        let trans_id: u32 = 1;
+        let trans_status = TransactionStatus {
+            amount: to_amount("1.0"),
+            deposit: false,
+            dispute: true,
+            ..Default::default()
+        };
+
+        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
+        trans_map.insert(trans_id, trans_status);
+
+        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
+
+        assert!(!does_transaction_exist_without_dispute(&trans_id, trans_map_opt));
+    }
+
+    #[test]
+    fn test_does_transaction_exist_without_dispute_no_dispute() {
+        let trans_id: u32 = 1;
+        let trans_status = TransactionStatus {
+            amount: to_amount("1.0"),
+            deposit: false,
+            dispute: false,
+            ..Default::default()
+        };
+
+        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
+        trans_map.insert(trans_id, trans_status);
+
+        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
+
+        assert!(does_transaction_exist_without_dispute(&trans_id, trans_map_opt));
+    }
+
+    #[test]
+    fn test_does_deposit_transaction_exist_with_dispute_is_withdrawal() {
+        let trans_id: u32 = 1;
+        let trans_status = TransactionStatus {
+            amount: to_amount("1.0"),
+            deposit: false,
+            dispute: true,
+            ..Default::default()
+        };
+
+        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
+        trans_map.insert(trans_id, trans_status);
+
+        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
+
+        assert!(!does_deposit_transaction_exist_with_dispute(&trans_id, trans_map_opt));
+    }
+
+    #[test]
+    fn test_does_deposit_transaction_exist_with_dispute_is_deposit() {
+        let trans_id: u32 = 1;
+        let trans_status = TransactionStatus {
+            amount: to_amount("1.0"),
+            deposit: true,
+            dispute: true,
+            ..Default::default()
+        };
+
+        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
+        trans_map.insert(trans_id, trans_status);
+
+        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
+
+        assert!(does_deposit_transaction_exist_with_dispute(&trans_id, trans_map_opt));
+    }
+
+    #[test]
+    fn test_does_transaction_exist_with_dispute_has_dispute() {
+        let trans_id: u32 = 1;
+        let trans_status = TransactionStatus {
+            amount: to_amount("1.0"),
+            deposit: false,
+            dispute: true,
+            ..Default::default()
+        };
+
+        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
+        trans_map.insert(trans_id, trans_status);
+
+        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
+
+        assert!(does_transaction_exist_with_dispute(&trans_id, trans_map_opt));
+    }
+
+    #[test]
+    fn test_does_transaction_exist_with_dispute_no_dispute() {
+        let trans_id: u32 = 1;
+        let trans_status = TransactionStatus {
+            amount: to_amount("1.0"),
+            deposit: false,
+            dispute: false,
+            ..Default::default()
+        };
+
+        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
+        trans_map.insert(trans_id, trans_status);
+
+        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
+
+        assert!(!does_transaction_exist_with_dispute(&trans_id, trans_map_opt));
+    }
+
+    #[test]
+    fn test_does_transaction_exist_no_transaction_map() {
+        let trans_id: u32 = 1;
+        assert!(!does_transaction_exist(&trans_id, None));
+    }
+
+    #[test]
+    fn test_does_transaction_exist_no_key_in_transaction_map() {
+        let trans_id: u32 = 1;
+        let other_trans_id: u32 = 2;
+        let trans_status = TransactionStatus {
+            amount: to_amount("1.0"),
+            deposit: false,
+            dispute: false,
+            ..Default::default()
+        };
+
+        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
+        trans_map.insert(other_trans_id, trans_status);
+
+        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
+
+        assert!(!does_transaction_exist(&trans_id, trans_map_opt));
+    }
+
+    #[test]
+    fn test_does_transaction_exist_with_transaction() {
+        let trans_id: u32 = 1;
+        let trans_status = TransactionStatus {
+            amount: to_amount("1.0"),
+            deposit: false,
+            dispute: false,
+            ..Default::default()
+        };
+
+        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
+        trans_map.insert(trans_id, trans_status);
+
+        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
+
+        assert!(does_transaction_exist(&trans_id, trans_map_opt));
+    }
+
+    #[test]
+    fn test_is_client_locked_none_account() {
+        assert!(!is_client_locked(None));
+    }
+
+    #[test]
+    fn test_is_client_locked_with_account_unlocked() {
+        let account = AccountInfo {
+            available: to_amount("0.0"),
+            held: to_amount("0.0"),
+            total: to_amount("0.0"),
+            locked: false,
+        };
+
+        let account_opt: Option<&AccountInfo> = Option::Some(&account);
+
+        assert!(!is_client_locked(account_opt));
+    }
+
+    #[test]
+    fn test_is_client_locked_with_account_locked() {
+        let account = AccountInfo {
+            available: to_amount("0.0"),
+            held: to_amount("0.0"),
+            total: to_amount("0.0"),
+            locked: true,
+        };
+
+        let account_opt: Option<&AccountInfo> = Option::Some(&account);
+
+        assert!(is_client_locked(account_opt));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,106 @@
+use std::io;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::engine::Engine;
+
+// calls waiting for the engine before callers have to wait to queue more, bounds memory when callers outpace the engine
+const QUEUED_CALLS: usize = 1024;
+
+type Call = Box<dyn FnOnce(&mut Engine) + Send>;
+
+// runs an engine on its own thread and lets async code use it, applying is cpu work and never waits on a connection
+// calls run one at a time in the order they were made, calls made one after another from the same task are never reordered
+// cloning the handle shares the engine, it stops once every handle is dropped
+#[derive(Clone)]
+pub struct EngineHandle {
+    calls: mpsc::Sender<Call>,
+}
+
+impl EngineHandle {
+    pub fn spawn(engine: Engine) -> EngineHandle {
+        let (calls, receiver) = mpsc::channel::<Call>(QUEUED_CALLS);
+        thread::spawn(move || {
+            let mut engine = engine;
+            let mut receiver = receiver;
+            while let Some(call) = receiver.blocking_recv() {
+                call(&mut engine);
+                // nobody takes ledger events from a shared engine, they would only pile up
+                engine.take_ledger();
+            }
+        });
+        EngineHandle { calls }
+    }
+
+    // runs f on the engine and waits for its result
+    pub async fn call<T, F>(&self, f: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Engine) -> T + Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        self.send(move |engine| {
+            let _ = reply.send(f(engine));
+        })
+        .await?;
+        result.await.map_err(|_| engine_stopped())
+    }
+
+    // queues f without waiting for it to run, for callers that do not need the result
+    pub async fn send<F: FnOnce(&mut Engine) + Send + 'static>(&self, f: F) -> io::Result<()> {
+        self.calls.send(Box::new(f)).await.map_err(|_| engine_stopped())
+    }
+
+    // moves the engine's clock to the wall clock, in seconds since the unix epoch, once every period
+    // a server's rows may not carry timestamps, without this an open dispute would wait for one to be settled at its deadline
+    // the task does not keep the engine running, it ends once every other handle is dropped
+    pub fn drive_clock(&self, period: Duration) -> JoinHandle<()> {
+        let calls = self.calls.downgrade();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(period);
+            loop {
+                ticks.tick().await;
+                let Some(calls) = calls.upgrade() else {
+                    return;
+                };
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+                if (EngineHandle { calls }).send(move |engine| engine.advance_clock(now)).await.is_err() {
+                    return;
+                }
+            }
+        })
+    }
+}
+
+// the engine thread only goes away by panicking
+fn engine_stopped() -> io::Error {
+    io::Error::other("engine stopped")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::test_support::transaction;
+    use crate::transaction::Transaction;
+
+    #[tokio::test]
+    async fn test_drive_clock_settles_disputes_past_their_deadline() {
+        let mut engine = Engine::with_config(EngineConfig { resolve_within: Some(60), ..Default::default() });
+        engine.apply(Transaction { timestamp: Some(0), ..transaction("deposit", 1, 1, "1.0") }).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "")).unwrap();
+
+        // the wall clock is long past the deadline of 60 seconds after the epoch
+        let handle = EngineHandle::spawn(engine);
+        let ticker = handle.drive_clock(Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let disputed = handle.call(|engine| engine.transaction(1, 1).unwrap().dispute).await.unwrap();
+        assert!(!disputed);
+
+        drop(handle);
+        ticker.await.unwrap();
+    }
+}
@@ -0,0 +1,324 @@
+use std::io;
+
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use tokio::net::TcpListener;
+
+use crate::currency::Currency;
+use crate::engine::{Engine, TxError};
+use crate::handle::EngineHandle;
+use crate::output::{sorted_accounts, AccountRow, SortOrder};
+use crate::transaction::{Transaction, TransactionStatus};
+
+// body of every error response, reason is only there for rejected transactions
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tx: Option<u32>,
+    // rejected, malformed, not_found or internal
+    error: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+    message: String,
+}
+
+impl ErrorBody {
+    fn new(error: &'static str, message: String) -> ErrorBody {
+        ErrorBody { tx: None, error, reason: None, message }
+    }
+}
+
+struct ApiError(StatusCode, ErrorBody);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(self.1)).into_response()
+    }
+}
+
+impl From<io::Error> for ApiError {
+    fn from(err: io::Error) -> ApiError {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, ErrorBody::new("internal", err.to_string()))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Applied {
+    tx: u32,
+    outcome: &'static str,
+}
+
+// one entry per transaction of a batch, in the order they were sent
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum BatchResult {
+    Applied(Applied),
+    Rejected(ErrorBody),
+}
+
+#[derive(Debug, Serialize)]
+struct TransactionRow<'a> {
+    tx: u32,
+    client: u16,
+    #[serde(flatten)]
+    status: &'a TransactionStatus,
+}
+
+// the routes, on top of a shared engine
+// transactions are json objects with the csv column names, amounts are strings like "1.5" as in every json output
+pub fn router(engine: EngineHandle) -> Router {
+    Router::new()
+        .route("/transactions", post(submit))
+        .route("/transactions/{tx}", get(transaction))
+        .route("/accounts", get(accounts))
+        .route("/accounts/{client}", get(account))
+        .route("/accounts/{client}/{currency}", get(account_in))
+        .with_state(engine)
+}
+
+pub async fn serve_http(listener: TcpListener, engine: EngineHandle) -> io::Result<()> {
+    axum::serve(listener, router(engine)).await
+}
+
+fn apply(engine: &mut Engine, transaction: Transaction) -> Result<Applied, ErrorBody> {
+    let tx = transaction.id;
+    match engine.apply(transaction) {
+        Ok(outcome) => Ok(Applied { tx, outcome: outcome.as_str() }),
+        Err(err) => {
+            let TxError::Rejected(reason) = err;
+            Err(ErrorBody { tx: Some(tx), error: "rejected", reason: Some(reason.as_str()), message: err.to_string() })
+        }
+    }
+}
+
+// POST /transactions takes one transaction or an array of them
+// one transaction answers 200 with its outcome or 422 with why it was rejected
+// an array is applied in order without other requests in between and always answers 200 with a result per transaction
+async fn submit(State(engine): State<EngineHandle>, body: Bytes) -> Result<Response, ApiError> {
+    let malformed = |err: serde_json::Error| ApiError(StatusCode::BAD_REQUEST, ErrorBody::new("malformed", err.to_string()));
+    let mut value: serde_json::Value = serde_json::from_slice(&body).map_err(malformed)?;
+    amounts_as_text(&mut value);
+
+    if value.is_array() {
+        let transactions: Vec<Transaction> = serde_json::from_value(value).map_err(malformed)?;
+        let results = engine
+            .call(|engine| {
+                let results = transactions.into_iter().map(|transaction| match apply(engine, transaction) {
+                    Ok(applied) => BatchResult::Applied(applied),
+                    Err(rejected) => BatchResult::Rejected(rejected),
+                });
+                results.collect::<Vec<BatchResult>>()
+            })
+            .await?;
+        return Ok(Json(results).into_response());
+    }
+
+    let transaction: Transaction = serde_json::from_value(value).map_err(malformed)?;
+    match engine.call(|engine| apply(engine, transaction)).await? {
+        Ok(applied) => Ok(Json(applied).into_response()),
+        Err(rejected) => Err(ApiError(StatusCode::UNPROCESSABLE_ENTITY, rejected)),
+    }
+}
+
+// a json number amount, e.g. 1.5, arrives as a float, it is turned back into its shortest decimal text and parsed like "1.5"
+// that is exact up to 15 significant digits, larger amounts have to be strings
+// Amount itself only reads text, csv would otherwise hand it every amount as a float
+fn amounts_as_text(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Array(items) => items.iter_mut().for_each(amounts_as_text),
+        serde_json::Value::Object(fields) => {
+            if let Some(amount) = fields.get_mut("amount").filter(|amount| amount.is_number()) {
+                *amount = serde_json::Value::String(amount.to_string());
+            }
+        }
+        _ => {}
+    }
+}
+
+// GET /accounts, every account by client id, one entry per currency it holds
+async fn accounts(State(engine): State<EngineHandle>) -> Result<Json<Vec<AccountRow>>, ApiError> {
+    let rows = engine
+        .call(|engine| {
+            let accounts = sorted_accounts(engine, SortOrder::Client);
+            accounts.into_iter().map(|(client, currency, account_info)| AccountRow::new(client, currency, account_info)).collect()
+        })
+        .await?;
+    Ok(Json(rows))
+}
+
+fn parse_client(client: &str) -> Result<u16, ApiError> {
+    client.parse().map_err(|_| ApiError(StatusCode::BAD_REQUEST, ErrorBody::new("malformed", format!("invalid client '{}'", client))))
+}
+
+// GET /accounts/{client}, the balance in the default currency
+async fn account(State(engine): State<EngineHandle>, Path(client): Path<String>) -> Result<Json<AccountRow>, ApiError> {
+    let client = parse_client(&client)?;
+    let row = engine.call(move |engine| engine.account(client).map(|account_info| AccountRow::new(client, None, account_info))).await?;
+    row.map(Json).ok_or_else(|| ApiError(StatusCode::NOT_FOUND, ErrorBody::new("not_found", format!("no account for client {}", client))))
+}
+
+// GET /accounts/{client}/{currency}, the balance in a named currency
+async fn account_in(State(engine): State<EngineHandle>, Path((client, currency)): Path<(String, String)>) -> Result<Json<AccountRow>, ApiError> {
+    let client = parse_client(&client)?;
+    let currency: Currency = currency.parse().map_err(|_| ApiError(StatusCode::BAD_REQUEST, ErrorBody::new("malformed", format!("invalid currency '{}'", currency))))?;
+    let row = engine
+        .call(move |engine| engine.account_in(client, currency).map(|account_info| AccountRow::new(client, Some(currency), account_info)))
+        .await?;
+    row.map(Json).ok_or_else(|| ApiError(StatusCode::NOT_FOUND, ErrorBody::new("not_found", format!("no {} account for client {}", currency, client))))
+}
+
+// GET /transactions/{tx}, the amount and dispute state of a deposit or withdrawal
+async fn transaction(State(engine): State<EngineHandle>, Path(trans_id): Path<String>) -> Result<Response, ApiError> {
+    let trans_id: u32 = trans_id.parse().map_err(|_| ApiError(StatusCode::BAD_REQUEST, ErrorBody::new("malformed", format!("invalid tx '{}'", trans_id))))?;
+    // serialized on the engine thread so the status does not have to be cloned out
+    let row = engine
+        .call(move |engine| {
+            let (client, status) = engine.find_transaction(trans_id)?;
+            serde_json::to_value(TransactionRow { tx: trans_id, client, status }).ok()
+        })
+        .await?;
+    match row {
+        Some(row) => Ok(Json(row).into_response()),
+        None => Err(ApiError(StatusCode::NOT_FOUND, ErrorBody::new("not_found", format!("no transaction {}", trans_id)))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    async fn start() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_http(listener, EngineHandle::spawn(Engine::new())));
+        addr
+    }
+
+    // a bare http/1.1 request, returns the status code and the json body
+    async fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_http_submit_single_and_query() {
+        let addr = start().await;
+
+        let (status, body) = request(addr, "POST", "/transactions", r#"{"type":"deposit","client":1,"tx":1,"amount":"10.5"}"#).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, json!({"tx": 1, "outcome": "deposited"}));
+
+        let (status, body) = request(addr, "POST", "/transactions", r#"{"type":"dispute","client":1,"tx":1}"#).await;
+        assert_eq!((status, body["outcome"].as_str()), (200, Some("disputed")));
+
+        let (status, body) = request(addr, "GET", "/accounts/1", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body, json!({"client": 1, "available": "0.0000", "held": "10.5000", "total": "10.5000", "locked": false}));
+
+        let (status, body) = request(addr, "GET", "/transactions/1", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            json!({"tx": 1, "client": 1, "amount": "10.5000", "deposit": true, "dispute": true, "disputes": [{"id": 1, "amount": "10.5000"}], "disputed": "10.5000", "opened_disputes": 1})
+        );
+
+        let (status, body) = request(addr, "GET", "/accounts", "").await;
+        assert_eq!((status, body.as_array().unwrap().len()), (200, 1));
+    }
+
+    #[tokio::test]
+    async fn test_http_account_per_currency() {
+        let addr = start().await;
+
+        request(addr, "POST", "/transactions", r#"{"type":"deposit","client":1,"tx":1,"amount":"2.0"}"#).await;
+        let (status, _) = request(addr, "POST", "/transactions", r#"{"type":"deposit","client":1,"tx":2,"amount":"7.0","currency":"eur"}"#).await;
+        assert_eq!(status, 200);
+
+        let (status, body) = request(addr, "GET", "/accounts/1/eur", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body, json!({"client": 1, "currency": "EUR", "available": "7.0000", "held": "0.0000", "total": "7.0000", "locked": false}));
+
+        let (status, body) = request(addr, "GET", "/accounts", "").await;
+        assert_eq!((status, body.as_array().unwrap().len()), (200, 2));
+
+        let (status, _) = request(addr, "GET", "/accounts/1/usd", "").await;
+        assert_eq!(status, 404);
+        let (status, _) = request(addr, "GET", "/accounts/1/euro", "").await;
+        assert_eq!(status, 400);
+    }
+
+    #[tokio::test]
+    async fn test_http_rejection_is_a_structured_error() {
+        let addr = start().await;
+
+        let (status, body) = request(addr, "POST", "/transactions", r#"{"type":"withdrawal","client":1,"tx":1,"amount":"1.0"}"#).await;
+        assert_eq!(status, 422);
+        assert_eq!(
+            body,
+            json!({"tx": 1, "error": "rejected", "reason": "unknown_account", "message": "transaction rejected: unknown_account"})
+        );
+
+        // a json number is read like the same amount written as a string
+        let (status, _) = request(addr, "POST", "/transactions", r#"{"type":"deposit","client":1,"tx":2,"amount":1.5}"#).await;
+        assert_eq!(status, 200);
+        let (status, _) = request(addr, "POST", "/transactions", r#"[{"type":"deposit","client":1,"tx":3,"amount":2}]"#).await;
+        assert_eq!(status, 200);
+        let (_, body) = request(addr, "GET", "/accounts/1", "").await;
+        assert_eq!(body["available"], json!("3.5000"));
+
+        let (status, body) = request(addr, "POST", "/transactions", r#"{"type":"deposit","client":1,"tx":4,"amount":1.00001}"#).await;
+        assert_eq!((status, body["error"].as_str()), (400, Some("malformed")));
+
+        let (status, body) = request(addr, "GET", "/accounts/9", "").await;
+        assert_eq!((status, body["error"].as_str()), (404, Some("not_found")));
+
+        let (status, body) = request(addr, "GET", "/transactions/abc", "").await;
+        assert_eq!((status, body["error"].as_str()), (400, Some("malformed")));
+    }
+
+    #[tokio::test]
+    async fn test_http_batch_reports_each_transaction() {
+        let addr = start().await;
+
+        let batch = r#"[
+            {"type":"deposit","client":2,"tx":1,"amount":"3.0"},
+            {"type":"withdrawal","client":2,"tx":2,"amount":"5.0"},
+            {"type":"withdrawal","client":2,"tx":3,"amount":"1.0"}
+        ]"#;
+        let (status, body) = request(addr, "POST", "/transactions", batch).await;
+
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            json!([
+                {"tx": 1, "outcome": "deposited"},
+                {"tx": 2, "error": "rejected", "reason": "insufficient_funds", "message": "transaction rejected: insufficient_funds"},
+                {"tx": 3, "outcome": "withdrew"},
+            ])
+        );
+        let (_, body) = request(addr, "GET", "/accounts/2", "").await;
+        assert_eq!(body["available"], "2.0000");
+    }
+}
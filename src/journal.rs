@@ -0,0 +1,224 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::{Engine, Outcome, TxError};
+use crate::reader::InputPosition;
+use crate::transaction::Transaction;
+
+// one journaled transaction, written before the engine touches any state
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    // sequence number, carries on across runs through the engine snapshot
+    pub seq: u64,
+    // where the input row after this one starts, reading resumes here after a crash
+    pub next: InputPosition,
+    pub transaction: Transaction,
+}
+
+// append-only write-ahead log of transactions handed to the engine, one json entry per line
+// every transaction is journaled, rejected ones too, replaying the journal rejects them again
+// entries are written straight to the file without buffering so they survive the process dying
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    // opens or creates the journal and returns the entries already in it
+    // a half written last entry from a crash is cut off so new entries start on a clean line
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<(Journal, Vec<JournalEntry>)> {
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+
+        let mut entries = vec![];
+        let mut valid_len: u64 = 0;
+        let mut reader = BufReader::new(&file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+
+            match serde_json::from_str::<JournalEntry>(&line) {
+                Ok(entry) => entries.push(entry),
+                Err(_) => break,
+            }
+            valid_len += read as u64;
+        }
+
+        file.set_len(valid_len)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok((Journal { file }, entries))
+    }
+
+    pub fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+
+    // empties the journal once its entries are covered by a snapshot or the run finished
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_all()
+    }
+}
+
+impl Engine {
+    // journals the transaction, then applies it
+    // the outer result is the journal write, nothing is applied when it fails
+    pub fn apply_journaled(&mut self, journal: &mut Journal, next: InputPosition, transaction: Transaction) -> io::Result<Result<Outcome, TxError>> {
+        let entry = JournalEntry { seq: self.journal_seq + 1, next, transaction };
+        journal.append(&entry)?;
+
+        self.journal_seq = entry.seq;
+        Ok(self.apply(entry.transaction))
+    }
+
+    // re-applies journal entries the engine has not seen yet, e.g. on top of the snapshot a crashed run started from
+    // returns where the input should be resumed, None when there was nothing to replay
+    pub fn replay_journal(&mut self, entries: Vec<JournalEntry>) -> Option<InputPosition> {
+        let mut resume = None;
+        for entry in entries {
+            // entries at or below journal_seq are already part of the snapshot, only where they ended matters
+            resume = Some(entry.next);
+            if entry.seq <= self.journal_seq {
+                continue;
+            }
+
+            self.journal_seq = entry.seq;
+            let _ = self.apply(entry.transaction);
+        }
+        resume
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::reader::{ParseMode, TransactionReader};
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    const INPUT: &str = "type,client,tx,amount\n\
+                         deposit,1,1,10.0\n\
+                         withdrawal,1,2,3.0\n\
+                         deposit,2,3,5.0\n\
+                         dispute,2,3,\n\
+                         withdrawal,1,4,50.0\n";
+
+    fn journal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("oxidized_transactions_{}_{}.journal", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    // processes the input with a journal, stopping after `rows` rows like a crash would
+    fn run(engine: &mut Engine, journal: &mut Journal, resume: Option<InputPosition>, rows: usize) {
+        let mut reader = TransactionReader::new(Cursor::new(INPUT), ParseMode::Lenient).unwrap();
+        if let Some(position) = resume {
+            reader.seek(position).unwrap();
+        }
+
+        for row in reader.take(rows) {
+            let row = row.unwrap();
+            let _ = engine.apply_journaled(journal, row.next, row.transaction.unwrap()).unwrap();
+        }
+    }
+
+    fn uninterrupted() -> Engine {
+        let mut engine = Engine::new();
+        for row in TransactionReader::new(Cursor::new(INPUT), ParseMode::Lenient).unwrap() {
+            let _ = engine.apply(row.unwrap().transaction.unwrap());
+        }
+        engine
+    }
+
+    #[test]
+    fn test_journal_recovery_matches_uninterrupted_run() {
+        let path = journal_path("recovery");
+
+        // first run dies after three rows
+        let (mut journal, entries) = Journal::open(&path).unwrap();
+        assert!(entries.is_empty());
+        let mut crashed = Engine::new();
+        run(&mut crashed, &mut journal, None, 3);
+        drop(journal);
+
+        // second run rebuilds from the journal and reads only the rest of the input
+        let (mut journal, entries) = Journal::open(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        let mut recovered = Engine::new();
+        let resume = recovered.replay_journal(entries);
+        assert_eq!(resume.unwrap().line, 5);
+        run(&mut recovered, &mut journal, resume, usize::MAX);
+
+        assert_eq!(recovered.accounts, uninterrupted().accounts);
+        assert_eq!(recovered.transaction_status, uninterrupted().transaction_status);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_skips_entries_already_in_snapshot() {
+        let path = journal_path("snapshot");
+
+        // the run finished and saved its snapshot, but died before the journal was emptied
+        let (mut journal, _) = Journal::open(&path).unwrap();
+        let mut engine = Engine::new();
+        run(&mut engine, &mut journal, None, usize::MAX);
+        let mut snapshot = vec![];
+        engine.save_snapshot(&mut snapshot).unwrap();
+        drop(journal);
+
+        let (_, entries) = Journal::open(&path).unwrap();
+        let mut resumed = Engine::load_snapshot(snapshot.as_slice(), EngineConfig::default()).unwrap();
+        let resume = resumed.replay_journal(entries);
+
+        // nothing is applied twice and the whole input is marked as read
+        assert_eq!(resumed.accounts, uninterrupted().accounts);
+        assert_eq!(resume.unwrap().line, 7);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_cuts_off_torn_entry() {
+        let path = journal_path("torn");
+
+        let (mut journal, _) = Journal::open(&path).unwrap();
+        let mut engine = Engine::new();
+        run(&mut engine, &mut journal, None, 2);
+        journal.file.write_all(b"{\"seq\":3,\"ne").unwrap();
+        drop(journal);
+
+        let (mut journal, entries) = Journal::open(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        // new entries start on a clean line
+        run(&mut engine, &mut journal, entries.last().map(|entry| entry.next), 1);
+        drop(journal);
+        let (_, entries) = Journal::open(&path).unwrap();
+        assert_eq!(entries.iter().map(|entry| entry.seq).collect::<Vec<u64>>(), vec![1, 2, 3]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_journal_checkpoint_empties_journal() {
+        let path = journal_path("checkpoint");
+
+        let (mut journal, _) = Journal::open(&path).unwrap();
+        let mut engine = Engine::new();
+        run(&mut engine, &mut journal, None, 2);
+        journal.checkpoint().unwrap();
+        drop(journal);
+
+        let (_, entries) = Journal::open(&path).unwrap();
+        assert!(entries.is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+}
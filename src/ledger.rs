@@ -0,0 +1,264 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::account::AccountInfo;
+use crate::amount::Amount;
+use crate::currency::Currency;
+
+// which kind of transaction moved the balance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    // the two sides of a transfer, one event for the sender and one for the receiver
+    TransferOut,
+    TransferIn,
+    // a fee charged with a withdrawal or chargeback, it has the tx id of the transaction it was charged on
+    Fee,
+    // a withdrawal fee paid back when the withdrawal was disputed and resolved
+    FeeRefund,
+    // admin rows that lock or unlock a client, one event per balance of the client, nothing moves
+    Freeze,
+    Unlock,
+}
+
+// one balance movement, emitted for every applied transaction
+// the deltas are what changed, the balances are what the account looked like afterwards
+// total always moves by delta_available + delta_held
+// the balances are those of the event's currency, locked is the client's and holds for all its currencies
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerEvent {
+    // increases by one per event, carries on across runs through the engine snapshot
+    pub seq: u64,
+    pub client: u16,
+    // left out for the default currency
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    pub tx: u32,
+    pub kind: EventKind,
+    pub delta_available: Amount,
+    pub delta_held: Amount,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    // why an admin row froze or unlocked the client, for the audit trail, left out for every other event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+// the events do not add up to the balances they recorded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedgerError {
+    // folding the deltas does not fit in an Amount
+    Overflow { seq: u64 },
+    // folding the deltas gives different balances than the event recorded
+    Mismatch { seq: u64 },
+}
+
+impl fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerError::Overflow { seq } => write!(f, "ledger event {} overflows the balance", seq),
+            LedgerError::Mismatch { seq } => write!(f, "ledger event {} does not match the balances before it", seq),
+        }
+    }
+}
+
+impl std::error::Error for LedgerError {}
+
+// rebuilds account state from nothing but the events, in the order they were emitted
+// every event is checked against the balances it recorded, so a gap or an edit in the ledger is caught
+// a recovered run emits the events of its replayed journal entries again, events with a seq already folded are skipped
+// accounts are keyed by client and currency, None being the default currency
+pub fn fold_events<'a, I: IntoIterator<Item = &'a LedgerEvent>>(events: I) -> Result<HashMap<(u16, Option<Currency>), AccountInfo>, LedgerError> {
+    let mut accounts: HashMap<(u16, Option<Currency>), AccountInfo> = HashMap::new();
+    // a lock shows up on the event of the currency that caused it, the client's other currencies are locked too
+    let mut locked_clients = HashSet::new();
+    let mut last_seq = None;
+    for event in events {
+        if last_seq.is_some_and(|seq| event.seq <= seq) {
+            continue;
+        }
+        last_seq = Some(event.seq);
+
+        let overflow = LedgerError::Overflow { seq: event.seq };
+        let account = accounts.entry((event.client, event.currency)).or_insert(AccountInfo {
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            total: Amount::ZERO,
+            locked: false,
+        });
+        let available = account.available.checked_add(event.delta_available).ok_or(overflow.clone())?;
+        let held = account.held.checked_add(event.delta_held).ok_or(overflow.clone())?;
+        let total = available.checked_add(held).ok_or(overflow)?;
+
+        if (available, held, total) != (event.available, event.held, event.total) {
+            return Err(LedgerError::Mismatch { seq: event.seq });
+        }
+
+        *account = AccountInfo { available, held, total, locked: event.locked };
+        if event.locked {
+            locked_clients.insert(event.client);
+        } else {
+            locked_clients.remove(&event.client);
+        }
+    }
+
+    for ((client, _), account) in accounts.iter_mut() {
+        account.locked = locked_clients.contains(client);
+    }
+    Ok(accounts)
+}
+
+// appends events as one json object per line
+pub fn write_events<W: Write>(output: &mut W, events: &[LedgerEvent]) -> io::Result<()> {
+    for event in events {
+        serde_json::to_writer(&mut *output, event)?;
+        output.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+// reads events written by write_events
+// a last line without a newline was cut off mid write and is ignored
+pub fn read_events<R: BufRead>(input: R) -> io::Result<Vec<LedgerEvent>> {
+    let mut events = vec![];
+    let mut input = input;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 || !line.ends_with('\n') {
+            return Ok(events);
+        }
+
+        events.push(serde_json::from_str(&line)?);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::test_support::transaction;
+    use crate::transaction::Transaction;
+
+    fn engine_with_history() -> Engine {
+        let mut engine = Engine::new();
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(transaction("withdrawal", 1, 2, "3.0")).unwrap();
+        engine.apply(transaction("deposit", 2, 3, "5.0")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("resolve", 1, 2, "")).unwrap();
+        engine.apply(transaction("dispute", 2, 3, "")).unwrap();
+        engine.apply(transaction("chargeback", 2, 3, "")).unwrap();
+        let _ = engine.apply(transaction("withdrawal", 1, 4, "100.0"));
+        engine
+    }
+
+    fn balances(engine: &Engine) -> HashMap<(u16, Option<Currency>), AccountInfo> {
+        engine.balances().map(|(client, currency, account_info)| ((client, currency), account_info.clone())).collect()
+    }
+
+    #[test]
+    fn test_every_applied_transaction_emits_one_event() {
+        let engine = engine_with_history();
+
+        let kinds: Vec<EventKind> = engine.ledger().iter().map(|event| event.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                EventKind::Deposit,
+                EventKind::Withdrawal,
+                EventKind::Deposit,
+                EventKind::Dispute,
+                EventKind::Resolve,
+                EventKind::Dispute,
+                EventKind::Chargeback,
+            ]
+        );
+        let seqs: Vec<u64> = engine.ledger().iter().map(|event| event.seq).collect();
+        assert_eq!(seqs, (1..=7).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_event_records_deltas_and_resulting_balances() {
+        let engine = engine_with_history();
+
+        // withdrawal dispute is a provisional credit to held
+        let dispute = &engine.ledger()[3];
+        assert_eq!((dispute.client, dispute.tx), (1, 2));
+        assert_eq!(dispute.delta_available, Amount::ZERO);
+        assert_eq!(dispute.delta_held, "3.0".parse().unwrap());
+        assert_eq!(dispute.total, "10.0".parse().unwrap());
+
+        let chargeback = &engine.ledger()[6];
+        assert_eq!(chargeback.delta_held, "-5.0".parse().unwrap());
+        assert_eq!(chargeback.total, Amount::ZERO);
+        assert!(chargeback.locked);
+    }
+
+    #[test]
+    fn test_fold_events_rebuilds_accounts() {
+        let engine = engine_with_history();
+
+        assert_eq!(fold_events(engine.ledger()).unwrap(), balances(&engine));
+    }
+
+    #[test]
+    fn test_fold_events_rebuilds_every_currency_and_locks_the_client() {
+        let mut engine = Engine::new();
+        let in_currency = |trans_type, client, id, amount, currency: &str| Transaction { currency: currency.parse().ok(), ..transaction(trans_type, client, id, amount) };
+        engine.apply(in_currency("deposit", 1, 1, "10.0", "")).unwrap();
+        engine.apply(in_currency("deposit", 1, 2, "4.0", "EUR")).unwrap();
+        engine.apply(in_currency("deposit", 1, 3, "2.0", "usd")).unwrap();
+        engine.apply(in_currency("dispute", 1, 2, "", "")).unwrap();
+        engine.apply(in_currency("chargeback", 1, 2, "", "")).unwrap();
+
+        let events = engine.ledger();
+        assert_eq!(events[1].currency, "EUR".parse().ok());
+        assert_eq!((events[4].currency, events[4].total), ("EUR".parse().ok(), Amount::ZERO));
+
+        let accounts = fold_events(events).unwrap();
+        assert_eq!(accounts, balances(&engine));
+        assert_eq!(accounts.len(), 3);
+        assert!(accounts.values().all(|account| account.locked));
+    }
+
+    #[test]
+    fn test_fold_events_catches_missing_event() {
+        let engine = engine_with_history();
+        let mut events = engine.ledger().to_vec();
+        events.remove(1);
+
+        // client 1 is next seen in the dispute, which expects the withdrawal to have happened
+        assert_eq!(fold_events(&events), Err(LedgerError::Mismatch { seq: 4 }));
+    }
+
+    #[test]
+    fn test_fold_events_skips_repeated_events() {
+        let engine = engine_with_history();
+        let mut events = engine.ledger().to_vec();
+        events.insert(3, events[2].clone());
+
+        assert_eq!(fold_events(&events).unwrap(), balances(&engine));
+    }
+
+    #[test]
+    fn test_events_round_trip_through_ndjson() {
+        let engine = engine_with_history();
+        let mut output = vec![];
+        write_events(&mut output, engine.ledger()).unwrap();
+        output.extend_from_slice(b"{\"seq\":8,");
+
+        let events = read_events(output.as_slice()).unwrap();
+        assert_eq!(events, engine.ledger());
+    }
+}
@@ -0,0 +1,44 @@
+// oxidized_transactions is a toy payments engine
+// an Engine owns client accounts and their transaction history, transactions are applied one at a time
+// the binary in main.rs is a thin csv wrapper around this library, bin/server.rs feeds it from tcp connections
+// and bin/api.rs serves it over http, both start up through ServiceOptions
+
+mod account;
+mod amount;
+mod books;
+mod config;
+mod currency;
+mod engine;
+mod handle;
+mod http;
+mod journal;
+mod ledger;
+mod output;
+mod parallel;
+mod reader;
+mod report;
+mod server;
+mod service;
+mod snapshot;
+#[cfg(test)]
+mod test_support;
+mod transaction;
+
+pub use account::AccountInfo;
+pub use amount::{Amount, ParseAmountError};
+pub use books::{postings, write_trial_balance, BookAccount, Books, BooksError, Posting, SubAccount};
+pub use config::{parse_duration, DeadlineAction, EngineConfig, Fee, FeeSchedule, LockPolicy, WithdrawalDisputes};
+pub use currency::{Currency, ParseCurrencyError};
+pub use engine::{Engine, Outcome, RejectReason, TxError};
+pub use handle::EngineHandle;
+pub use http::{router, serve_http};
+pub use journal::{Journal, JournalEntry};
+pub use ledger::{fold_events, read_events, write_events, EventKind, LedgerError, LedgerEvent};
+pub use output::{sorted_accounts, write_accounts, OutputFormat, SortOrder};
+pub use parallel::ShardedEngine;
+pub use reader::{InputPosition, ParseError, ParseMode, RecordParser, Row, TransactionReader};
+pub use report::RejectedReport;
+pub use server::serve;
+pub use service::ServiceOptions;
+pub use snapshot::{SnapshotError, SNAPSHOT_VERSION};
+pub use transaction::{Dispute, Transaction, TransactionStatus};
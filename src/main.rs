@@ -1,1241 +1,320 @@
-use std::collections::HashMap;
 use std::env;
-use std::fs;
-
-use csv::Error;
-use serde::Deserialize;
-use serde_with::{serde_as, DefaultOnError};
-
-#[derive(Debug)]
-struct AccountInfo {
-    available: f64,
-    held: f64,
-    total: f64,
-    locked: bool,
-}
-
-#[derive(Debug)]
-struct TransactionStatus {
-    amount: f64,
-    // chargebacks should only happen on a deposit if i understand correctly
-    deposit: bool,
-    dispute: bool,
-}
-
-#[serde_as]
-#[derive(Debug, Deserialize)]
-struct Transaction {
-    #[serde(rename = "type")]
-    #[serde_as(deserialize_as = "DefaultOnError")]
-    trans_type: String,
-
-    #[serde_as(deserialize_as = "DefaultOnError")]
-    client: u16,
-
-    #[serde(rename = "tx")]
-    #[serde_as(deserialize_as = "DefaultOnError")]
-    id: u32,
-
-    #[serde_as(deserialize_as = "DefaultOnError")]
-    amount: f64,
-}
-
-fn main() -> Result<(), Error> {
-    let args: Vec<String> = env::args().collect();
-
-    let filename = &args[1];
-    let transaction_data = fs::read_to_string(filename)
-        .expect("Something went wrong reading the file :(");
-
-    let mut rdr = csv::Reader::from_reader(transaction_data.as_bytes());
-
-    // map to track account data
-    // key: client
-    // value: AccountInfo struct
-    let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-
-    // tracks transactions for dispute/resolve/chargeback
-    // drops all transactions for client once client account is locked
-    // key: tx
-    // value: TransactionStatus Struct
-    let mut transaction_status: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-    for result in rdr.deserialize() {
-        let transaction: Transaction = result?;
-        handle_record(&mut accounts, &mut transaction_status, transaction)
-    }
-
-    println!("client,available,held,total,locked");
-    for (client, account_info) in &accounts {
-        println!("{},{},{},{},{}",
-                 client, account_info.available, account_info.held, account_info.total, account_info.locked);
-    }
-
-    Ok(())
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::process;
+
+use csv::StringRecord;
+use oxidized_transactions::{sorted_accounts, write_accounts, write_events, write_trial_balance, Engine, EngineConfig, InputPosition, Journal, OutputFormat, Outcome, ParseError, ParseMode, RejectReason, RejectedReport, ShardedEngine, SortOrder, Transaction, TransactionReader, TxError};
+
+const USAGE: &str = "usage: oxidized_transactions <transactions.csv | -> [--rejected <rejected.csv>] [--strict] [--sort client|total|locked] [--format csv|json|ndjson]
+       [--resume-from <snapshot.json>] [--snapshot-to <snapshot.json>] [--journal <journal.ndjson>] [--ledger <ledger.ndjson>]
+       [--trial-balance <trial_balance.csv>] [--threads <n>] [<engine options>]";
+
+// command line options
+// the input path is required, "-" reads transactions from stdin
+struct Options {
+    input: String,
+    rejected: Option<String>,
+    // --strict stops at the first malformed row instead of defaulting its fields
+    mode: ParseMode,
+    // order of the account rows, client id ascending unless --sort says otherwise
+    sort: SortOrder,
+    format: OutputFormat,
+    config: EngineConfig,
+    // engine state to start from instead of empty accounts
+    resume_from: Option<String>,
+    // where to write the engine state once the input is processed
+    snapshot_to: Option<String>,
+    // write-ahead journal, a run that died is recovered from it and picks up where it stopped
+    journal: Option<String>,
+    // every balance movement is appended here as a ledger event
+    ledger: Option<String>,
+    // checks the books once the input is processed and writes their trial balance here
+    trial_balance: Option<String>,
+    // worker threads, more than one shards the accounts by client id
+    threads: usize,
 }
 
-fn handle_record(mut accounts: &mut HashMap<u16, AccountInfo>, mut transaction_status: &mut HashMap<u16, HashMap<u32, TransactionStatus>>, transaction: Transaction) {
-    let amount = transaction.amount;
-    let client = transaction.client;
-    let trans_type = transaction.trans_type.to_lowercase();
-    let trans_id = transaction.id;
-
-    // only handles records with valid data
-    // amount can be 0.0 (default f64) in the case of withdrawal, dispute, chargeback
-    // exit early if amount 0.0 for deposit or withdrawal, it is invalid
-    if transaction.client > 0 && transaction.id > 0 && !is_client_locked(accounts.get(&client)) {
-        match &*trans_type {
-            "deposit" => {
-                if amount == 0.0 {
-                    return;
-                }
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut input = None;
+    let mut rejected = None;
+    let mut mode = ParseMode::Lenient;
+    let mut sort = SortOrder::Client;
+    let mut format = OutputFormat::Csv;
+    let mut config = EngineConfig::default();
+    let mut resume_from = None;
+    let mut snapshot_to = None;
+    let mut journal = None;
+    let mut ledger = None;
+    let mut trial_balance = None;
+    let mut threads = 1;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if config.parse_flag(arg, &mut args)? {
+            continue;
+        }
 
-                handle_deposit(&mut accounts, &mut transaction_status, amount, &client, trans_id);
+        match arg.as_str() {
+            "--rejected" => {
+                let path = args.next().ok_or("--rejected needs a file path")?;
+                rejected = Some(path.clone());
             }
-            "withdrawal" => {
-                if amount == 0.0 {
-                    return;
-                }
-
-                handle_withdrawal(&mut accounts, &mut transaction_status, amount, &client, trans_id)
+            "--strict" => mode = ParseMode::Strict,
+            "--sort" => {
+                let order = args.next().ok_or("--sort needs client, total or locked")?;
+                sort = order.parse()?;
             }
-            "dispute" => {
-                handle_dispute(&mut accounts, &mut transaction_status, &client, &trans_id)
+            "--format" => {
+                let name = args.next().ok_or("--format needs csv, json or ndjson")?;
+                format = name.parse()?;
             }
-            "resolve" => {
-                handle_resolve(&mut accounts, &mut transaction_status, &client, &trans_id)
+            "--resume-from" => {
+                let path = args.next().ok_or("--resume-from needs a snapshot file path")?;
+                resume_from = Some(path.clone());
             }
-            "chargeback" => {
-                handle_chargeback(&mut accounts, &mut transaction_status, &client, &trans_id)
+            "--snapshot-to" => {
+                let path = args.next().ok_or("--snapshot-to needs a snapshot file path")?;
+                snapshot_to = Some(path.clone());
             }
-            _ => {}
+            "--journal" => {
+                let path = args.next().ok_or("--journal needs a file path")?;
+                journal = Some(path.clone());
+            }
+            "--ledger" => {
+                let path = args.next().ok_or("--ledger needs a file path")?;
+                ledger = Some(path.clone());
+            }
+            "--trial-balance" => {
+                let path = args.next().ok_or("--trial-balance needs a file path")?;
+                trial_balance = Some(path.clone());
+            }
+            "--threads" => {
+                let count = args.next().ok_or("--threads needs a number of worker threads")?;
+                threads = count.parse().ok().filter(|count| *count > 0).ok_or_else(|| format!("invalid thread count '{}'", count))?;
+            }
+            _ if input.is_none() => input = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
         }
     }
-}
-
-fn handle_chargeback(accounts: &mut HashMap<u16, AccountInfo>, transaction_status: &mut HashMap<u16, HashMap<u32, TransactionStatus>>, client: &u16, trans_id: &u32) {
-    // handle_chargeback removes money from held and total  if a client exists and the transaction exists with a dispute
-    // chargeback will then update the account map (client -> account) with new numbers, moving an amount from held to available
-    // this will also update the transactions map in the case there is a future dispute
-    let trans_status = transaction_status.get(&client);
-
-    // deposit from client is reversed, which is a chargeback
-    // not checking chargebacks for withdrawals
-    if does_deposit_transaction_exist_with_dispute(&trans_id, trans_status) {
-        let current_account = accounts.get(&client).unwrap();
-        let dispute_amount = trans_status.unwrap().get(&trans_id).unwrap().amount;
-        let account_info = AccountInfo {
-            available: current_account.available,
-            held: current_account.held - dispute_amount,
-            total: current_account.held + current_account.available - dispute_amount,
-            locked: true,
-        };
-
-        // insert locked account, nothing should pass ever again
-        accounts.insert(*client, account_info);
 
-        // remove client from transactions, save memory
-        transaction_status.remove(client);
+    let input = input.ok_or("missing transactions file")?;
+    // shards apply rows out of input order and number their ledger events on their own
+    if threads > 1 && (journal.is_some() || ledger.is_some()) {
+        return Err("--threads cannot be combined with --journal or --ledger".to_string());
     }
-}
-
-fn handle_resolve(accounts: &mut HashMap<u16, AccountInfo>, transaction_status: &mut HashMap<u16, HashMap<u32, TransactionStatus>>, client: &u16, trans_id: &u32) {
-    // handle_resolve only moves money from held to available if a client exists and the transaction exists with a dispute
-    // resolve will then update the account map (client -> account) with new numbers, moving an amount from held to available
-    // this will also update the transactions map in the case there is a future dispute
-    let trans_status = transaction_status.get(&client);
-    if does_transaction_exist_with_dispute(&trans_id, trans_status) {
-        let current_account = accounts.get(&client).unwrap();
-        let dispute_amount = trans_status.unwrap().get(&trans_id).unwrap().amount;
-        let account_info = AccountInfo {
-            available: current_account.available + dispute_amount,
-            held: current_account.held - dispute_amount,
-            total: current_account.held + current_account.available,
-            locked: false,
-        };
-
-        let is_deposit = trans_status.unwrap().get(trans_id).unwrap().deposit;
-        let updated_status = TransactionStatus { amount: dispute_amount, deposit: is_deposit, dispute: false };
-
-        accounts.insert(*client, account_info);
-        transaction_status.get_mut(&client).unwrap().insert(*trans_id, updated_status);
+    // settling a deadline needs every account at the same point in time, see ShardedEngine
+    if threads > 1 && config.resolve_within.is_some() {
+        return Err("--threads cannot be combined with --resolve-within".to_string());
     }
-}
 
-fn handle_dispute(accounts: &mut HashMap<u16, AccountInfo>, transaction_status: &mut HashMap<u16, HashMap<u32, TransactionStatus>>, client: &u16, trans_id: &u32) {
-    // handle_dispute only moves money from available to held if a client exists and the transaction exists
-    // dispute will then update the account map (client -> account) with new numbers, moving an amount from available to held
-    // this will also update the transactions map in the case there is a future resolve/chargeback
-    let trans_status = transaction_status.get(&client);
-    if does_transaction_exist_without_dispute(&trans_id, trans_status) {
-        let current_account = accounts.get(&client).unwrap();
-        let dispute_amount = trans_status.unwrap().get(&trans_id).unwrap().amount;
-        let account_info = AccountInfo {
-            available: current_account.available - dispute_amount,
-            held: current_account.held + dispute_amount,
-            total: current_account.held + current_account.available,
-            locked: false,
-        };
+    Ok(Options { input, rejected, mode, sort, format, config, resume_from, snapshot_to, journal, ledger, trial_balance, threads })
+}
 
-        let is_deposit = trans_status.unwrap().get(trans_id).unwrap().deposit;
-        let updated_status = TransactionStatus { amount: dispute_amount, deposit: is_deposit, dispute: true };
+// the transactions source, only a file can be seeked to resume reading after a crash
+enum Input {
+    Stdin(io::StdinLock<'static>),
+    File(BufReader<File>),
+}
 
-        accounts.insert(*client, account_info);
-        transaction_status.get_mut(&client).unwrap().insert(*trans_id, updated_status);
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::Stdin(stdin) => stdin.read(buf),
+            Input::File(file) => file.read(buf),
+        }
     }
 }
 
-fn handle_withdrawal(accounts: &mut HashMap<u16, AccountInfo>, transactions: &mut HashMap<u16, HashMap<u32, TransactionStatus>>, amount: f64, client: &u16, trans_id: u32) {
-    // handle_withdrawal only withdraws if a client exists with available funds over 0.0
-    // withdrawal will then update the account map (client -> account) with new numbers withdrawn
-    // this will also update the transactions map in the case there is a future dispute/resolve/chargeback
-    let trans_status = TransactionStatus { amount, deposit: false, dispute: false };
-    let account = accounts.get(&client);
-
-    if !account.is_none() {
-        let current_account = account.unwrap();
-        if current_account.available - amount >= 0.0 {
-            let account_info = AccountInfo {
-                available: current_account.available - amount,
-                held: current_account.held,
-                total: current_account.held + current_account.available - amount,
-                locked: false,
-            };
-
-            accounts.insert(*client, account_info);
-            transactions.get_mut(client).unwrap().insert(trans_id, trans_status);
+impl Seek for Input {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Input::Stdin(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "cannot resume reading stdin, the input must be a file")),
+            Input::File(file) => file.seek(pos),
         }
     }
 }
 
-fn handle_deposit(accounts: &mut HashMap<u16, AccountInfo>, transactions: &mut HashMap<u16, HashMap<u32, TransactionStatus>>, amount: f64, client: &u16, trans_id: u32) {
-    // handle_deposit creates client if client does not exist
-    // deposit will then update the account map (client -> account) with new numbers deposited
-    // this will also update the transactions map in the case there is a future dispute/resolve/chargeback
-    let trans_status = TransactionStatus { amount, deposit: true, dispute: false };
-    let account = accounts.get(&client);
-    if account.is_none() {
-        let account_info = AccountInfo {
-            available: amount,
-            held: 0.0,
-            total: amount,
-            locked: false,
-        };
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = parse_args(&args).unwrap_or_else(|err| {
+        eprintln!("{}\n{}\n{}", err, USAGE, EngineConfig::USAGE);
+        process::exit(2);
+    });
 
-        accounts.insert(*client, account_info);
+    // records are read and applied one at a time, memory is bounded by account state and not by input size
+    let input = if options.input == "-" {
+        Input::Stdin(io::stdin().lock())
     } else {
-        let current_account = account.unwrap();
-        let account_info = AccountInfo {
-            available: current_account.available + amount,
-            held: current_account.held,
-            total: current_account.held + current_account.available + amount,
-            locked: false,
-        };
+        Input::File(BufReader::new(File::open(&options.input)?))
+    };
+
+    // ledger events are appended, so the file keeps growing across resumed runs
+    let mut ledger = match &options.ledger {
+        Some(path) => Some(BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?)),
+        None => None,
+    };
+
+    let mut engine = match &options.resume_from {
+        Some(path) => Engine::load_snapshot_file(path, options.config.clone())?,
+        None => Engine::with_config(options.config.clone()),
+    };
+
+    let mut reader = TransactionReader::new(input, options.mode)?;
+
+    // the journal holds what a previous run applied after its snapshot, the input is resumed after the last of it
+    // rows that run rejected are not reported again
+    let mut recovered = false;
+    let mut journal = match &options.journal {
+        Some(path) => {
+            let (journal, entries) = Journal::open(path)?;
+            if let Some(position) = engine.replay_journal(entries) {
+                reader.seek(position)?;
+                recovered = true;
+            }
+            Some(journal)
+        }
+        None => None,
+    };
+
+    // every row that does nothing is written to the rejected report, if asked for
+    // a recovered run adds to the report of the run that crashed, which has the rows before where reading resumes
+    let mut report = match &options.rejected {
+        Some(path) if recovered => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            match file.metadata()?.len() {
+                0 => Some(RejectedReport::new(BufWriter::new(file))?),
+                _ => Some(RejectedReport::appending(BufWriter::new(file))),
+            }
+        }
+        Some(path) => Some(RejectedReport::new(BufWriter::new(File::create(path)?))?),
+        None => None,
+    };
 
-        accounts.insert(*client, account_info);
+    // replayed entries emit their events again, some may already be in the ledger, fold_events skips those
+    let events = engine.take_ledger();
+    if let Some(ledger) = ledger.as_mut() {
+        write_events(ledger, &events)?;
     }
 
-    if transactions.get(client).is_none() {
-        let mut init_map: HashMap<u32, TransactionStatus> = HashMap::new();
-        init_map.insert(trans_id, trans_status);
-        transactions.insert(*client, init_map);
+    // a strict run stops at the first malformed row
+    let mut failed = None;
+    if options.threads > 1 {
+        (engine, failed) = process_sharded(engine, reader, &options, report.as_mut())?;
     } else {
-        transactions.get_mut(client).unwrap().insert(trans_id, trans_status);
-    }
-}
-
-fn does_transaction_exist_without_dispute(trans_id: &u32, trans_status: Option<&HashMap<u32, TransactionStatus>>) -> bool {
-    // used to find a transaction exists for a dispute record based on the transaction id
-    does_transaction_exist(&trans_id, trans_status) && !trans_status.unwrap().get(&trans_id).unwrap().dispute
-}
-
-fn does_deposit_transaction_exist_with_dispute(trans_id: &u32, trans_status: Option<&HashMap<u32, TransactionStatus>>) -> bool {
-    // used to find a deposit transaction exists for a chargeback record based on the transaction id
-    does_transaction_exist_with_dispute(trans_id, trans_status) && trans_status.unwrap().get(&trans_id).unwrap().deposit
-}
-
-fn does_transaction_exist_with_dispute(trans_id: &u32, trans_status: Option<&HashMap<u32, TransactionStatus>>) -> bool {
-    // used to find a transaction exists for a resolve record based on the transaction id
-    does_transaction_exist(&trans_id, trans_status) && trans_status.unwrap().get(&trans_id).unwrap().dispute
-}
-
-fn does_transaction_exist(trans_id: &&u32, trans_status: Option<&HashMap<u32, TransactionStatus>>) -> bool {
-    // used to find a transaction exists for a dispute record
-    !trans_status.is_none() && !trans_status.unwrap().get(&trans_id).is_none()
-}
-
-fn is_client_locked(account: Option<&AccountInfo>) -> bool {
-    if !account.is_none() {
-        return account.unwrap().locked;
-    }
-
-    return false;
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_handle_record_two_clients_three_deposits_two_withdrawals_one_dispute(){
-        let client1deposit1 = Transaction{
-            trans_type: "Deposit".to_string(),
-            client: 1,
-            id: 1,
-            amount: 2.0
-        };
-
-        let client1withdrawal1 = Transaction{
-            trans_type: "Withdrawal".to_string(),
-            client: 1,
-            id: 2,
-            amount: 1.0
-        };
-
-        let client2deposit2 = Transaction{
-            trans_type: "Deposit".to_string(),
-            client: 2,
-            id: 3,
-            amount: 3.0
-        };
-
-        let client2withdrawal2 = Transaction{
-            trans_type: "Withdrawal".to_string(),
-            client: 2,
-            id: 4,
-            amount: 1.75
-        };
-
-        let client2deposit3 = Transaction{
-            trans_type: "Deposit".to_string(),
-            client: 2,
-            id: 5,
-            amount: 3.0
-        };
-
-        let client2dispute1 = Transaction{
-            trans_type: "Dispute".to_string(),
-            client: 2,
-            id: 5,
-            amount: 0.0
-        };
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        handle_record(&mut accounts, &mut transactions, client1deposit1);
-        handle_record(&mut accounts, &mut transactions, client1withdrawal1);
-        handle_record(&mut accounts, &mut transactions, client2deposit2);
-        handle_record(&mut accounts, &mut transactions, client2withdrawal2);
-        handle_record(&mut accounts, &mut transactions, client2deposit3);
-        handle_record(&mut accounts, &mut transactions, client2dispute1);
-
-        let expected_client1_account_info = AccountInfo{
-            available: 1.0,
-            held: 0.0,
-            total: 1.0,
-            locked: false
-        };
-
-        let expected_client2_account_info = AccountInfo{
-            available: 1.25,
-            held: 3.0,
-            total: 4.25,
-            locked: false
-        };
-
-        let client1_account = accounts.get(&1).unwrap();
-        assert_eq!(client1_account.total, expected_client1_account_info.total);
-        assert_eq!(client1_account.held, expected_client1_account_info.held);
-        assert_eq!(client1_account.available, expected_client1_account_info.available);
-        assert_eq!(client1_account.locked, expected_client1_account_info.locked);
-
-        let client2_account = accounts.get(&2).unwrap();
-        assert_eq!(client2_account.total, expected_client2_account_info.total);
-        assert_eq!(client2_account.held, expected_client2_account_info.held);
-        assert_eq!(client2_account.available, expected_client2_account_info.available);
-        assert_eq!(client2_account.locked, expected_client2_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_record_three_deposits_two_withdrawals_one_dispute_one_resolve(){
-        let client1deposit1 = Transaction{
-            trans_type: "Deposit".to_string(),
-            client: 1,
-            id: 1,
-            amount: 2.0
-        };
-
-        let client1withdrawal1 = Transaction{
-            trans_type: "Withdrawal".to_string(),
-            client: 1,
-            id: 2,
-            amount: 1.0
-        };
-
-        let client2deposit2 = Transaction{
-            trans_type: "Deposit".to_string(),
-            client: 2,
-            id: 3,
-            amount: 3.0
-        };
-
-        let client2withdrawal2 = Transaction{
-            trans_type: "Withdrawal".to_string(),
-            client: 2,
-            id: 4,
-            amount: 1.75
-        };
-
-        let client2deposit3 = Transaction{
-            trans_type: "Deposit".to_string(),
-            client: 2,
-            id: 5,
-            amount: 3.0
-        };
-
-        let client2dispute1 = Transaction{
-            trans_type: "Dispute".to_string(),
-            client: 2,
-            id: 5,
-            amount: 0.0
-        };
-
-        let client2resolve1 = Transaction{
-            trans_type: "Resolve".to_string(),
-            client: 2,
-            id: 5,
-            amount: 0.0
-        };
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        handle_record(&mut accounts, &mut transactions, client1deposit1);
-        handle_record(&mut accounts, &mut transactions, client1withdrawal1);
-        handle_record(&mut accounts, &mut transactions, client2deposit2);
-        handle_record(&mut accounts, &mut transactions, client2withdrawal2);
-        handle_record(&mut accounts, &mut transactions, client2deposit3);
-        handle_record(&mut accounts, &mut transactions, client2dispute1);
-        handle_record(&mut accounts, &mut transactions, client2resolve1);
-
-        let expected_client1_account_info = AccountInfo{
-            available: 1.0,
-            held: 0.0,
-            total: 1.0,
-            locked: false
-        };
-
-        let expected_client2_account_info = AccountInfo{
-            available: 4.25,
-            held: 0.0,
-            total: 4.25,
-            locked: false
-        };
-
-        let client1_account = accounts.get(&1).unwrap();
-        assert_eq!(client1_account.total, expected_client1_account_info.total);
-        assert_eq!(client1_account.held, expected_client1_account_info.held);
-        assert_eq!(client1_account.available, expected_client1_account_info.available);
-        assert_eq!(client1_account.locked, expected_client1_account_info.locked);
-
-        let client2_account = accounts.get(&2).unwrap();
-        assert_eq!(client2_account.total, expected_client2_account_info.total);
-        assert_eq!(client2_account.held, expected_client2_account_info.held);
-        assert_eq!(client2_account.available, expected_client2_account_info.available);
-        assert_eq!(client2_account.locked, expected_client2_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_record_three_deposits_two_withdrawals_one_dispute_one_chargeback(){
-        let client1deposit1 = Transaction{
-            trans_type: "Deposit".to_string(),
-            client: 1,
-            id: 1,
-            amount: 2.0
-        };
-
-        let client1withdrawal1 = Transaction{
-            trans_type: "Withdrawal".to_string(),
-            client: 1,
-            id: 2,
-            amount: 1.0
-        };
-
-        let client2deposit2 = Transaction{
-            trans_type: "Deposit".to_string(),
-            client: 2,
-            id: 3,
-            amount: 3.0
-        };
-
-        let client2withdrawal2 = Transaction{
-            trans_type: "Withdrawal".to_string(),
-            client: 2,
-            id: 4,
-            amount: 1.75
-        };
-
-        let client2deposit3 = Transaction{
-            trans_type: "Deposit".to_string(),
-            client: 2,
-            id: 5,
-            amount: 3.0
-        };
-
-        let client2dispute1 = Transaction{
-            trans_type: "Dispute".to_string(),
-            client: 2,
-            id: 5,
-            amount: 0.0
-        };
-
-        let client2resolve1 = Transaction{
-            trans_type: "Chargeback".to_string(),
-            client: 2,
-            id: 5,
-            amount: 0.0
-        };
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        handle_record(&mut accounts, &mut transactions, client1deposit1);
-        handle_record(&mut accounts, &mut transactions, client1withdrawal1);
-        handle_record(&mut accounts, &mut transactions, client2deposit2);
-        handle_record(&mut accounts, &mut transactions, client2withdrawal2);
-        handle_record(&mut accounts, &mut transactions, client2deposit3);
-        handle_record(&mut accounts, &mut transactions, client2dispute1);
-        handle_record(&mut accounts, &mut transactions, client2resolve1);
-
-        let expected_client1_account_info = AccountInfo{
-            available: 1.0,
-            held: 0.0,
-            total: 1.0,
-            locked: false
-        };
-
-        let expected_client2_account_info = AccountInfo{
-            available: 1.25,
-            held: 0.0,
-            total: 1.25,
-            locked: true
-        };
-
-        let client1_account = accounts.get(&1).unwrap();
-        assert_eq!(client1_account.total, expected_client1_account_info.total);
-        assert_eq!(client1_account.held, expected_client1_account_info.held);
-        assert_eq!(client1_account.available, expected_client1_account_info.available);
-        assert_eq!(client1_account.locked, expected_client1_account_info.locked);
-
-        let client2_account = accounts.get(&2).unwrap();
-        assert_eq!(client2_account.total, expected_client2_account_info.total);
-        assert_eq!(client2_account.held, expected_client2_account_info.held);
-        assert_eq!(client2_account.available, expected_client2_account_info.available);
-        assert_eq!(client2_account.locked, expected_client2_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_chargeback_with_deposit_disputed() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let trans_id2: u32 = 2;
-        let amount2: f64 = 2.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        let expected_account_info = AccountInfo{
-            available: 1.0,
-            held: 0.0,
-            total: 1.0,
-            locked: true
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-        handle_deposit(&mut accounts, &mut transactions, amount2, &client, trans_id2);
-        handle_dispute(&mut accounts, &mut transactions, &client, &trans_id2);
-        handle_chargeback(&mut accounts, &mut transactions, &client, &trans_id2);
-
-        // client transactions are removed from transactions map, do nothing now that account locked
-        let client_transactions = transactions.get(&client);
-        assert_eq!(client_transactions.is_none(), true);
-
-        // assert account numbers
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_chargeback_with_chargeback_duplicated() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let trans_id2: u32 = 2;
-        let amount2: f64 = 2.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        let expected_account_info = AccountInfo{
-            available: 1.0,
-            held: 0.0,
-            total: 1.0,
-            locked: true
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-        handle_deposit(&mut accounts, &mut transactions, amount2, &client, trans_id2);
-        handle_dispute(&mut accounts, &mut transactions, &client, &trans_id2);
-        handle_chargeback(&mut accounts, &mut transactions, &client, &trans_id2);
-        handle_chargeback(&mut accounts, &mut transactions, &client, &trans_id2);
-
-        // client transactions are removed from transactions map, do nothing now that account locked
-        let client_transactions = transactions.get(&client);
-        assert_eq!(client_transactions.is_none(), true);
-
-        // assert account numbers
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_chargeback_with_nonexistent_transaction_id() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let trans_id2: u32 = 2;
-        let amount2: f64 = 2.0;
-
-        let bad_id: u32 = 3;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        // funds stays held, no reversal
-        let expected_account_info = AccountInfo{
-            available: 1.0,
-            held: 2.0,
-            total: 3.0,
-            locked: false
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-        handle_deposit(&mut accounts, &mut transactions, amount2, &client, trans_id2);
-        handle_dispute(&mut accounts, &mut transactions, &client, &trans_id2);
-        handle_chargeback(&mut accounts, &mut transactions, &client, &bad_id);
-
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_resolve_with_deposit_disputed() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let trans_id2: u32 = 2;
-        let amount2: f64 = 2.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        // dispute resolved, expect false dispute
-        let expected_trans_status = TransactionStatus {
-            amount: 2.0,
-            deposit: true,
-            dispute: false,
-        };
-
-        let expected_account_info = AccountInfo{
-            available: 3.0,
-            held: 0.0,
-            total: 3.0,
-            locked: false
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-        handle_deposit(&mut accounts, &mut transactions, amount2, &client, trans_id2);
-        handle_dispute(&mut accounts, &mut transactions, &client, &trans_id2);
-        handle_resolve(&mut accounts, &mut transactions, &client, &trans_id2);
-
-        // assert second transaction exists
-        let actual_trans_status = transactions.get(&client).unwrap().get(&trans_id2).unwrap();
-        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
-        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
-        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
-
-        // assert account numbers
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_resolve_with_resolve_duplicated() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let trans_id2: u32 = 2;
-        let amount2: f64 = 2.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        // dispute resolved, expect false dispute
-        let expected_trans_status = TransactionStatus {
-            amount: 2.0,
-            deposit: true,
-            dispute: false,
-        };
-
-        let expected_account_info = AccountInfo{
-            available: 3.0,
-            held: 0.0,
-            total: 3.0,
-            locked: false
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-        handle_deposit(&mut accounts, &mut transactions, amount2, &client, trans_id2);
-        handle_dispute(&mut accounts, &mut transactions, &client, &trans_id2);
-        handle_resolve(&mut accounts, &mut transactions, &client, &trans_id2);
-        handle_resolve(&mut accounts, &mut transactions, &client, &trans_id2);
-
-        // assert second transaction exists
-        let actual_trans_status = transactions.get(&client).unwrap().get(&trans_id2).unwrap();
-        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
-        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
-        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
-
-        // assert account numbers
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_resolve_with_nonexistent_transaction_id() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let trans_id2: u32 = 2;
-        let amount2: f64 = 2.0;
-
-        let bad_id: u32 = 3;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        // funds stays held
-        let expected_account_info = AccountInfo{
-            available: 1.0,
-            held: 2.0,
-            total: 3.0,
-            locked: false
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-        handle_deposit(&mut accounts, &mut transactions, amount2, &client, trans_id2);
-        handle_dispute(&mut accounts, &mut transactions, &client, &trans_id2);
-        handle_resolve(&mut accounts, &mut transactions, &client, &bad_id);
-
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_dispute_with_deposits() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let trans_id2: u32 = 2;
-        let amount2: f64 = 2.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-
-        let expected_trans_status = TransactionStatus {
-            amount: 2.0,
-            deposit: true,
-            dispute: true,
-        };
-
-        let expected_account_info = AccountInfo{
-            available: 1.0,
-            held: 2.0,
-            total: 3.0,
-            locked: false
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-        handle_deposit(&mut accounts, &mut transactions, amount2, &client, trans_id2);
-        handle_dispute(&mut accounts, &mut transactions, &client, &trans_id2);
-
-        // assert second transaction exists
-        let actual_trans_status = transactions.get(&client).unwrap().get(&trans_id2).unwrap();
-        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
-        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
-        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
-
-        // assert account numbers
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_dispute_with_duplicate_dispute() {
-        // should not move to held funds more than once
-
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let trans_id2: u32 = 2;
-        let amount2: f64 = 2.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-
-        let expected_trans_status = TransactionStatus {
-            amount: 2.0,
-            deposit: true,
-            dispute: true,
-        };
-
-        let expected_account_info = AccountInfo{
-            available: 1.0,
-            held: 2.0,
-            total: 3.0,
-            locked: false
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-        handle_deposit(&mut accounts, &mut transactions, amount2, &client, trans_id2);
-        handle_dispute(&mut accounts, &mut transactions, &client, &trans_id2);
-        handle_dispute(&mut accounts, &mut transactions, &client, &trans_id2);
-
-        // assert second transaction exists
-        let actual_trans_status = transactions.get(&client).unwrap().get(&trans_id2).unwrap();
-        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
-        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
-        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
-
-        // assert account numbers
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_dispute_with_nonexistent_transaction_id() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let trans_id2: u32 = 2;
-        let amount2: f64 = 2.0;
-
-        let bad_id: u32 = 3;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        let expected_account_info = AccountInfo{
-            available: 3.0,
-            held: 0.0,
-            total: 3.0,
-            locked: false
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-        handle_deposit(&mut accounts, &mut transactions, amount2, &client, trans_id2);
-        handle_dispute(&mut accounts, &mut transactions, &client, &bad_id);
-
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_withdrawal_with_new_account() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        handle_withdrawal(&mut accounts, &mut transactions, amount, &client, trans_id);
-
-        // assert transaction exists
-        let trans_status = transactions.get(&client);
-        assert_eq!(trans_status.is_none(), true);
-
-        // assert account numbers
-        let account_info = accounts.get(&client);
-        assert_eq!(account_info.is_none(), true)
-    }
-
-    #[test]
-    fn test_handle_withdrawal_with_existing_account() {
-        let client: u16 = 1;
-        let deposit_trans_id: u32 = 1;
-        let withdrawal_trans_id: u32 = 2;
-        let amount: f64 = 1.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        let expected_withdrawal_trans_status = TransactionStatus {
-            amount: 1.0,
-            deposit: false,
-            dispute: false,
-        };
-
-        let expected_account_info = AccountInfo{
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
-            locked: false
-        };
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, deposit_trans_id);
-        handle_withdrawal(&mut accounts, &mut transactions, amount, &client, withdrawal_trans_id);
-
-        // assert transaction exists
-        let actual_trans_status = transactions.get(&client).unwrap().get(&withdrawal_trans_id).unwrap();
-        assert_eq!(expected_withdrawal_trans_status.amount, actual_trans_status.amount);
-        assert_eq!(expected_withdrawal_trans_status.deposit, actual_trans_status.deposit);
-        assert_eq!(expected_withdrawal_trans_status.dispute, actual_trans_status.dispute);
-
-        // assert account numbers
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_withdrawal_with_existing_account_insufficient_funds() {
-        let client: u16 = 1;
-        let deposit_trans_id: u32 = 1;
-        let withdrawal_trans_id: u32 = 2;
-        let withdrawal_amount: f64 = 2.0;
-        let amount: f64 = 1.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        let expected_account_info = AccountInfo{
-            available: 1.0,
-            held: 0.0,
-            total: 1.0,
-            locked: false
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, deposit_trans_id);
-        handle_withdrawal(&mut accounts, &mut transactions, withdrawal_amount, &client, withdrawal_trans_id);
-
-        // assert transaction exists
-        let actual_trans_status = transactions.get(&client).unwrap().get(&withdrawal_trans_id);
-        assert_eq!(actual_trans_status.is_none(), true);
-
-        // assert account numbers
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_deposit_with_new_account() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-        let expected_trans_status = TransactionStatus {
-            amount: 1.0,
-            deposit: true,
-            dispute: false,
-        };
-
-        let expected_account_info = AccountInfo{
-            available: 1.0,
-            held: 0.0,
-            total: 1.0,
-            locked: false
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-
-        // assert transaction exists
-        let actual_trans_status = transactions.get(&client).unwrap().get(&trans_id).unwrap();
-        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
-        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
-        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
-
-        // assert account numbers
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_handle_deposit_with_existing_account() {
-        let client: u16 = 1;
-        let trans_id: u32 = 1;
-        let amount: f64 = 1.0;
-
-        let trans_id2: u32 = 2;
-        let amount2: f64 = 2.0;
-
-        let mut accounts: HashMap<u16, AccountInfo> = HashMap::new();
-        let mut transactions: HashMap<u16, HashMap<u32, TransactionStatus>> = HashMap::new();
-
-
-        let expected_trans_status = TransactionStatus {
-            amount: 2.0,
-            deposit: true,
-            dispute: false,
-        };
-
-        let expected_account_info = AccountInfo{
-            available: 3.0,
-            held: 0.0,
-            total: 3.0,
-            locked: false
-        };
-
-        handle_deposit(&mut accounts, &mut transactions, amount, &client, trans_id);
-        handle_deposit(&mut accounts, &mut transactions, amount2, &client, trans_id2);
-
-        // assert second transaction exists
-        let actual_trans_status = transactions.get(&client).unwrap().get(&trans_id2).unwrap();
-        assert_eq!(expected_trans_status.amount, actual_trans_status.amount);
-        assert_eq!(expected_trans_status.deposit, actual_trans_status.deposit);
-        assert_eq!(expected_trans_status.dispute, actual_trans_status.dispute);
-
-        // assert account numbers
-        let actual_account_info = accounts.get(&client).unwrap();
-        assert_eq!(expected_account_info.available, actual_account_info.available);
-        assert_eq!(expected_account_info.held, actual_account_info.held);
-        assert_eq!(expected_account_info.total, actual_account_info.total);
-        assert_eq!(expected_account_info.locked, actual_account_info.locked);
-    }
-
-    #[test]
-    fn test_does_transaction_exist_without_dispute_has_dispute() {
-        let trans_id: u32 = 1;
-        let trans_status = TransactionStatus {
-            amount: 1.0,
-            deposit: false,
-            dispute: true,
-        };
-
-        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
-        trans_map.insert(trans_id, trans_status);
-
-        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
-
-        assert_eq!(does_transaction_exist_without_dispute(&&trans_id, trans_map_opt), false);
-    }
-
-    #[test]
-    fn test_does_transaction_exist_without_dispute_no_dispute() {
-        let trans_id: u32 = 1;
-        let trans_status = TransactionStatus {
-            amount: 1.0,
-            deposit: false,
-            dispute: false,
-        };
-
-        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
-        trans_map.insert(trans_id, trans_status);
+        // rejected transactions leave the engine untouched and are skipped
+        for row in reader {
+            let row = row?;
+            let rejection = match row.transaction {
+                Ok(transaction) => match apply(&mut engine, journal.as_mut(), row.next, transaction)? {
+                    Ok(_) => None,
+                    Err(TxError::Rejected(reason)) => {
+                        let detail = row.defaulted.map(|err| err.to_string()).unwrap_or_default();
+                        Some((reason.as_str(), detail))
+                    }
+                },
+                Err(err) if options.mode == ParseMode::Strict => {
+                    failed = Some(err);
+                    break;
+                }
+                Err(err) => Some(("malformed", err.to_string())),
+            };
 
-        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
+            if let (Some(report), Some((reason, detail))) = (report.as_mut(), rejection) {
+                report.write(row.line, &row.record, reason, &detail)?;
+            }
 
-        assert_eq!(does_transaction_exist_without_dispute(&&trans_id, trans_map_opt), true);
+            // taken every row so events do not pile up in the engine
+            let events = engine.take_ledger();
+            if let Some(ledger) = ledger.as_mut() {
+                write_events(ledger, &events)?;
+            }
+        }
     }
 
-    #[test]
-    fn test_does_deposit_transaction_exist_with_dispute_is_withdrawal() {
-        let trans_id: u32 = 1;
-        let trans_status = TransactionStatus {
-            amount: 1.0,
-            deposit: false,
-            dispute: true,
-        };
-
-        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
-        trans_map.insert(trans_id, trans_status);
-
-        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
-
-        assert_eq!(does_deposit_transaction_exist_with_dispute(&&trans_id, trans_map_opt), false);
+    if let Some(ledger) = ledger.as_mut() {
+        ledger.flush()?;
     }
 
-    #[test]
-    fn test_does_deposit_transaction_exist_with_dispute_is_deposit() {
-        let trans_id: u32 = 1;
-        let trans_status = TransactionStatus {
-            amount: 1.0,
-            deposit: true,
-            dispute: true,
-        };
-
-        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
-        trans_map.insert(trans_id, trans_status);
-
-        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
-
-        assert_eq!(does_deposit_transaction_exist_with_dispute(&&trans_id, trans_map_opt), true);
+    if let Some(report) = report.as_mut() {
+        report.flush()?;
     }
 
-    #[test]
-    fn test_does_transaction_exist_with_dispute_has_dispute() {
-        let trans_id: u32 = 1;
-        let trans_status = TransactionStatus {
-            amount: 1.0,
-            deposit: false,
-            dispute: true,
-        };
-
-        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
-        trans_map.insert(trans_id, trans_status);
-
-        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
-
-        assert_eq!(does_transaction_exist_with_dispute(&&trans_id, trans_map_opt), true);
+    // only once what the rows before it did has been written out
+    if let Some(err) = failed {
+        eprintln!("{}", err);
+        process::exit(1);
     }
 
-    #[test]
-    fn test_does_transaction_exist_with_dispute_no_dispute() {
-        let trans_id: u32 = 1;
-        let trans_status = TransactionStatus {
-            amount: 1.0,
-            deposit: false,
-            dispute: false,
-        };
-
-        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
-        trans_map.insert(trans_id, trans_status);
-
-        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
-
-        assert_eq!(does_transaction_exist_with_dispute(&&trans_id, trans_map_opt), false);
+    if let Some(path) = &options.trial_balance {
+        if let Err(err) = engine.check_books() {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        write_trial_balance(BufWriter::new(File::create(path)?), engine.books())?;
     }
 
-    #[test]
-    fn test_does_transaction_exist_no_transaction_map() {
-        let trans_id: u32 = 1;
-        assert_eq!(does_transaction_exist(&&trans_id, None), false);
+    if let Some(path) = &options.snapshot_to {
+        engine.save_snapshot_file(path)?;
     }
 
-    #[test]
-    fn test_does_transaction_exist_no_key_in_transaction_map() {
-        let trans_id: u32 = 1;
-        let other_trans_id: u32 = 2;
-        let trans_status = TransactionStatus {
-            amount: 1.0,
-            deposit: false,
-            dispute: false,
-        };
-
-        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
-        trans_map.insert(other_trans_id, trans_status);
+    let accounts = sorted_accounts(&engine, options.sort);
+    let mut output = BufWriter::new(io::stdout().lock());
+    write_accounts(&mut output, &accounts, options.format)?;
+    output.flush()?;
 
-        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
-
-        assert_eq!(does_transaction_exist(&&trans_id, trans_map_opt), false);
+    // everything in the journal has been applied, saved and written out, the next run starts clean
+    // checkpointed last, a run that fails before here can still be recovered
+    if let Some(journal) = journal.as_mut() {
+        journal.checkpoint()?;
     }
 
-    #[test]
-    fn test_does_transaction_exist_with_transaction() {
-        let trans_id: u32 = 1;
-        let trans_status = TransactionStatus {
-            amount: 1.0,
-            deposit: false,
-            dispute: false,
-        };
-
-        let mut trans_map: HashMap<u32, TransactionStatus> = HashMap::new();
-        trans_map.insert(trans_id, trans_status);
-
-        let trans_map_opt: Option<&HashMap<u32, TransactionStatus>> = Option::Some(&trans_map);
-
-        assert_eq!(does_transaction_exist(&&trans_id, trans_map_opt), true);
-    }
+    Ok(())
+}
 
-    #[test]
-    fn test_is_client_locked_none_account() {
-        assert_eq!(is_client_locked(None), false);
+// journals the transaction first when a journal is in use
+fn apply(engine: &mut Engine, journal: Option<&mut Journal>, next: InputPosition, transaction: Transaction) -> io::Result<Result<Outcome, TxError>> {
+    match journal {
+        Some(journal) => engine.apply_journaled(journal, next, transaction),
+        None => Ok(engine.apply(transaction)),
     }
+}
 
-    #[test]
-    fn test_is_client_locked_with_account_unlocked() {
-        let account = AccountInfo {
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
-            locked: false,
-        };
-
-        let account_opt: Option<&AccountInfo> = Option::Some(&account);
-
-        assert_eq!(is_client_locked(account_opt), false);
+// parses on this thread and applies the transactions on worker threads, see ShardedEngine
+// shards only hand their rejections back at the end, so all rejected rows are kept until then and reported in line order
+// in strict mode the first malformed row stops reading and comes back with the engine, the rows before it are still reported
+fn process_sharded<R: Read, W: Write>(engine: Engine, reader: TransactionReader<R>, options: &Options, report: Option<&mut RejectedReport<W>>) -> Result<(Engine, Option<ParseError>), Box<dyn Error>> {
+    let mut sharded = ShardedEngine::new(engine, options.threads);
+    let mut rejections = vec![];
+    let rejected = |(line, record, defaulted): (u64, StringRecord, Option<ParseError>), reason: RejectReason| {
+        (line, record, reason.as_str(), defaulted.map(|err| err.to_string()).unwrap_or_default())
+    };
+
+    let mut failed = None;
+    for row in reader {
+        let row = row?;
+        match row.transaction {
+            Ok(transaction) => {
+                if let Err((tag, reason)) = sharded.submit((row.line, row.record, row.defaulted), transaction) {
+                    rejections.push(rejected(tag, reason));
+                }
+            }
+            Err(err) if options.mode == ParseMode::Strict => {
+                failed = Some(err);
+                break;
+            }
+            Err(err) => rejections.push((row.line, row.record, "malformed", err.to_string())),
+        }
     }
 
-    #[test]
-    fn test_is_client_locked_with_account_locked() {
-        let account = AccountInfo {
-            available: 0.0,
-            held: 0.0,
-            total: 0.0,
-            locked: true,
-        };
-
-        let account_opt: Option<&AccountInfo> = Option::Some(&account);
-
-        assert_eq!(is_client_locked(account_opt), true);
+    let (engine, shard_rejections) = sharded.finish();
+    if let Some(report) = report {
+        rejections.extend(shard_rejections.into_iter().map(|(tag, reason)| rejected(tag, reason)));
+        rejections.sort_by_key(|(line, _, _, _)| *line);
+        for (line, record, reason, detail) in rejections {
+            report.write(line, &record, reason, &detail)?;
+        }
     }
-}
\ No newline at end of file
+    Ok((engine, failed))
+}
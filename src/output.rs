@@ -0,0 +1,252 @@
+use std::cmp::Reverse;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::account::AccountInfo;
+use crate::amount::Amount;
+use crate::currency::Currency;
+use crate::engine::Engine;
+
+// how account state is written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    // header row plus one row per account
+    #[default]
+    Csv,
+    // a single json array of accounts
+    Json,
+    // one json object per line
+    Ndjson,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            _ => Err(format!("unknown format '{}', expected csv, json or ndjson", s)),
+        }
+    }
+}
+
+// one output row, an AccountInfo with the client and currency it belongs to
+#[derive(Debug, Serialize)]
+pub(crate) struct AccountRow {
+    client: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    currency: Option<Currency>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+}
+
+impl AccountRow {
+    pub(crate) fn new(client: u16, currency: Option<Currency>, account_info: &AccountInfo) -> AccountRow {
+        AccountRow {
+            client,
+            currency,
+            available: account_info.available,
+            held: account_info.held,
+            total: account_info.total,
+            locked: account_info.locked,
+        }
+    }
+}
+
+// order of the account rows in the output
+// every order falls back to client id ascending then currency, so identical inputs always give identical outputs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    // client id ascending
+    #[default]
+    Client,
+    // largest total first
+    Total,
+    // locked accounts first
+    Locked,
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(SortOrder::Client),
+            "total" => Ok(SortOrder::Total),
+            "locked" => Ok(SortOrder::Locked),
+            _ => Err(format!("unknown sort order '{}', expected client, total or locked", s)),
+        }
+    }
+}
+
+// one row per client and currency it has a balance in, the default currency (None) before named ones
+// totals in different currencies are compared as they are
+pub fn sorted_accounts(engine: &Engine, order: SortOrder) -> Vec<(u16, Option<Currency>, &AccountInfo)> {
+    let mut accounts: Vec<(u16, Option<Currency>, &AccountInfo)> = engine.balances().collect();
+    match order {
+        SortOrder::Client => accounts.sort_by_key(|(client, currency, _)| (*client, *currency)),
+        SortOrder::Total => accounts.sort_by_key(|(client, currency, account_info)| (Reverse(account_info.total), *client, *currency)),
+        SortOrder::Locked => accounts.sort_by_key(|(client, currency, account_info)| (!account_info.locked, *client, *currency)),
+    }
+    accounts
+}
+
+// rows in a named currency carry it in a currency column, json rows in the default currency leave it out
+// csv only has the column when at least one row needs it, so output without currencies looks as it always did
+pub fn write_accounts<W: Write>(output: W, accounts: &[(u16, Option<Currency>, &AccountInfo)], format: OutputFormat) -> io::Result<()> {
+    let rows = accounts.iter().map(|(client, currency, account_info)| AccountRow::new(*client, *currency, account_info));
+    match format {
+        OutputFormat::Csv => {
+            // header is written by hand, serialize would only write it with the first row and skip it for no accounts
+            let mut wtr = csv::WriterBuilder::new().has_headers(false).from_writer(output);
+            let with_currency = accounts.iter().any(|(_, currency, _)| currency.is_some());
+            if with_currency {
+                wtr.write_record(["client", "currency", "available", "held", "total", "locked"])?;
+            } else {
+                wtr.write_record(["client", "available", "held", "total", "locked"])?;
+            }
+            for row in rows {
+                if with_currency {
+                    // an empty currency field for the default currency keeps the columns lined up
+                    wtr.serialize((row.client, row.currency, row.available, row.held, row.total, row.locked))?;
+                } else {
+                    wtr.serialize(row)?;
+                }
+            }
+            wtr.flush()
+        }
+        OutputFormat::Json => {
+            let mut output = output;
+            serde_json::to_writer(&mut output, &rows.collect::<Vec<AccountRow>>())?;
+            writeln!(output)?;
+            output.flush()
+        }
+        OutputFormat::Ndjson => {
+            let mut output = output;
+            for row in rows {
+                serde_json::to_writer(&mut output, &row)?;
+                writeln!(output)?;
+            }
+            output.flush()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::transaction;
+    use crate::transaction::Transaction;
+
+    fn engine_with_accounts() -> Engine {
+        // client 1 holds 1.0, client 2 holds 5.0 and is locked, client 3 holds 5.0
+        let rows = [
+            ("deposit", 3, 1, "5.0"),
+            ("deposit", 1, 2, "1.0"),
+            ("deposit", 2, 3, "5.0"),
+            ("deposit", 2, 4, "2.0"),
+            ("dispute", 2, 4, ""),
+            ("chargeback", 2, 4, ""),
+        ];
+
+        let mut engine = Engine::new();
+        for (trans_type, client, id, amount) in rows {
+            engine.apply(transaction(trans_type, client, id, amount)).unwrap();
+        }
+        engine
+    }
+
+    fn clients(accounts: Vec<(u16, Option<Currency>, &AccountInfo)>) -> Vec<u16> {
+        accounts.into_iter().map(|(client, _, _)| client).collect()
+    }
+
+    #[test]
+    fn test_sorted_accounts_by_client() {
+        let engine = engine_with_accounts();
+        assert_eq!(clients(sorted_accounts(&engine, SortOrder::Client)), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_sorted_accounts_by_total_ties_on_client() {
+        let engine = engine_with_accounts();
+        assert_eq!(clients(sorted_accounts(&engine, SortOrder::Total)), vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn test_sorted_accounts_by_locked() {
+        let engine = engine_with_accounts();
+        assert_eq!(clients(sorted_accounts(&engine, SortOrder::Locked)), vec![2, 1, 3]);
+    }
+
+    fn write(format: OutputFormat) -> String {
+        let engine = engine_with_accounts();
+        let mut output = vec![];
+        write_accounts(&mut output, &sorted_accounts(&engine, SortOrder::Client)[..2], format).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_write_accounts_csv() {
+        assert_eq!(write(OutputFormat::Csv), "client,available,held,total,locked\n\
+                                              1,1.0000,0.0000,1.0000,false\n\
+                                              2,5.0000,0.0000,5.0000,true\n");
+    }
+
+    #[test]
+    fn test_write_accounts_one_row_per_currency() {
+        let mut engine = engine_with_accounts();
+        for (id, currency) in [(10, "USD"), (11, "EUR")] {
+            engine.apply(Transaction { currency: currency.parse().ok(), ..transaction("deposit", 1, id, "2.5") }).unwrap();
+        }
+
+        let accounts = sorted_accounts(&engine, SortOrder::Client);
+        let mut csv = vec![];
+        write_accounts(&mut csv, &accounts[..3], OutputFormat::Csv).unwrap();
+        let mut ndjson = vec![];
+        write_accounts(&mut ndjson, &accounts[..2], OutputFormat::Ndjson).unwrap();
+
+        assert_eq!(String::from_utf8(csv).unwrap(), "client,currency,available,held,total,locked\n\
+                                                    1,,1.0000,0.0000,1.0000,false\n\
+                                                    1,EUR,2.5000,0.0000,2.5000,false\n\
+                                                    1,USD,2.5000,0.0000,2.5000,false\n");
+        assert_eq!(String::from_utf8(ndjson).unwrap(), "{\"client\":1,\"available\":\"1.0000\",\"held\":\"0.0000\",\"total\":\"1.0000\",\"locked\":false}\n\
+                                                       {\"client\":1,\"currency\":\"EUR\",\"available\":\"2.5000\",\"held\":\"0.0000\",\"total\":\"2.5000\",\"locked\":false}\n");
+    }
+
+    #[test]
+    fn test_write_accounts_csv_without_accounts_keeps_header() {
+        let mut output = vec![];
+        write_accounts(&mut output, &[], OutputFormat::Csv).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "client,available,held,total,locked\n");
+    }
+
+    #[test]
+    fn test_write_accounts_json() {
+        assert_eq!(write(OutputFormat::Json), "[{\"client\":1,\"available\":\"1.0000\",\"held\":\"0.0000\",\"total\":\"1.0000\",\"locked\":false},\
+                                               {\"client\":2,\"available\":\"5.0000\",\"held\":\"0.0000\",\"total\":\"5.0000\",\"locked\":true}]\n");
+    }
+
+    #[test]
+    fn test_write_accounts_ndjson() {
+        assert_eq!(write(OutputFormat::Ndjson), "{\"client\":1,\"available\":\"1.0000\",\"held\":\"0.0000\",\"total\":\"1.0000\",\"locked\":false}\n\
+                                                 {\"client\":2,\"available\":\"5.0000\",\"held\":\"0.0000\",\"total\":\"5.0000\",\"locked\":true}\n");
+    }
+
+    #[test]
+    fn test_output_format_from_str() {
+        assert_eq!("ndjson".parse::<OutputFormat>(), Ok(OutputFormat::Ndjson));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_sort_order_from_str() {
+        assert_eq!("total".parse::<SortOrder>(), Ok(SortOrder::Total));
+        assert!("balance".parse::<SortOrder>().is_err());
+    }
+}
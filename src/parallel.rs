@@ -0,0 +1,430 @@
+use std::collections::HashMap;
+use std::mem;
+use std::panic;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use crate::books::{BookAccount, SubAccount};
+use crate::engine::{check_row, index_trans_id, Engine, RejectReason};
+use crate::transaction::Transaction;
+
+// transactions go to a shard in batches, so the channel is paid for once per batch and not once per row
+const BATCH_SIZE: usize = 1024;
+
+// batches that can wait for a shard before submit blocks, bounds memory when one shard falls behind
+const QUEUED_BATCHES: usize = 16;
+
+enum Job<T> {
+    // a transaction on its way to a shard, owner comes from the dispatcher's tx id index
+    // borrow is set when the transaction also changes a client of another shard
+    // clock is the dispatcher's clock once it has seen the transaction, shards only see their own clients' rows
+    Apply { tag: T, transaction: Transaction, owner: Option<u16>, clock: Option<u64>, borrow: Option<Borrow> },
+    // hands a client over to the shard applying a transaction that borrows it and waits to get it back
+    Lend { client: u16, lent: SyncSender<Engine>, returned: Receiver<Engine> },
+}
+
+// the other end of a Lend, the client comes in and goes back as an Engine holding only that client
+struct Borrow {
+    client: u16,
+    lent: Receiver<Engine>,
+    returned: SyncSender<Engine>,
+}
+
+struct Shard<T> {
+    sender: SyncSender<Vec<Job<T>>>,
+    batch: Vec<Job<T>>,
+    worker: JoinHandle<(Engine, Vec<(T, RejectReason)>)>,
+}
+
+// applies transactions on worker threads, each owning the clients where client % shards is its index
+// a client always goes to the same shard and batches are sent in order, so each client's transactions are applied in input order
+// the tx id index is global and stays with the dispatcher, so duplicate ids and disputes naming another client's tx
+// are rejected exactly like Engine::apply would
+// a transfer between clients of two shards, or a dispute of one, is applied by the sender's shard once the receiver's shard
+// has lent it the receiver; both shards get the job at the same point of their input, so every shard sees the same order
+// the result is the same as applying everything to one engine, except that no ledger events are kept
+// resolution deadlines are not supported, settling them would need every shard to stop at the same time
+// T tags each transaction, e.g. with its input line, and comes back with the rejections
+pub struct ShardedEngine<T> {
+    // state that is not split across shards, the shards are merged back into it
+    base: Engine,
+    shards: Vec<Shard<T>>,
+    // receiver of every transfer submitted, key: tx
+    // disputes of a transfer change the receiver, the dispatcher needs to know which shard it is in
+    destinations: HashMap<u32, u16>,
+}
+
+impl<T: Send + 'static> ShardedEngine<T> {
+    // continues from engine, Engine::new() to start empty
+    // panics if shards is 0 or the engine's config sets resolve_within
+    pub fn new(engine: Engine, shards: usize) -> ShardedEngine<T> {
+        assert!(shards > 0, "a sharded engine needs at least one shard");
+        assert!(engine.config.resolve_within.is_none(), "a sharded engine does not support resolution deadlines");
+
+        let mut base = engine;
+        // transfers the engine already knows about can be disputed later on, their receivers have to be lent too
+        let destinations = base
+            .transaction_status
+            .values()
+            .flat_map(|statuses| statuses.iter())
+            .filter_map(|(trans_id, status)| status.destination.map(|destination| (*trans_id, destination)))
+            .collect();
+        let shards = base
+            .split(shards)
+            .into_iter()
+            .map(|engine| {
+                let (sender, receiver) = mpsc::sync_channel::<Vec<Job<T>>>(QUEUED_BATCHES);
+                let worker = thread::spawn(move || {
+                    let mut engine = engine;
+                    let mut rejections = vec![];
+                    for batch in receiver {
+                        for job in batch {
+                            if let Some((tag, reason)) = engine.run(job) {
+                                rejections.push((tag, reason));
+                            }
+                        }
+                        // event sequence numbers are per shard and would clash, so events are not kept
+                        engine.take_ledger();
+                    }
+                    (engine, rejections)
+                });
+                Shard { sender, batch: Vec::with_capacity(BATCH_SIZE), worker }
+            })
+            .collect();
+
+        ShardedEngine { base, shards, destinations }
+    }
+
+    // hands the transaction to its client's shard
+    // deposits and withdrawals reusing a tx id are rejected straight away, anything else is rejected by the shard
+    pub fn submit(&mut self, tag: T, transaction: Transaction) -> Result<(), (T, RejectReason)> {
+        let mut transaction = transaction;
+        transaction.trans_type = transaction.trans_type.to_lowercase();
+
+        let owner = match index_trans_id(&mut self.base.tx_clients, &transaction) {
+            Ok(owner) => owner,
+            Err(reason) => return Err((tag, reason)),
+        };
+
+        // only a row that passes the checks that need no shard moves the clock, the dispatcher cannot see balances or locks
+        // there are no deadlines in sharded mode, so a row its shard rejects moving the clock settles nothing
+        if let (Some(timestamp), Ok(())) = (transaction.timestamp, check_row(&transaction, owner)) {
+            self.base.clock = Some(self.base.clock.map_or(timestamp, |clock| clock.max(timestamp)));
+        }
+        let clock = self.base.clock;
+
+        // the other client a transaction changes, if any
+        let counterparty = if transaction.trans_type == "transfer" {
+            if let Some(destination) = transaction.destination {
+                self.destinations.insert(transaction.id, destination);
+            }
+            transaction.destination
+        } else {
+            self.destinations.get(&transaction.id).copied()
+        };
+
+        let index = usize::from(transaction.client) % self.shards.len();
+        let other = counterparty.map(|client| (client, usize::from(client) % self.shards.len())).filter(|(_, other)| *other != index);
+        let Some((client, other)) = other else {
+            let shard = &mut self.shards[index];
+            shard.batch.push(Job::Apply { tag, transaction, owner, clock, borrow: None });
+            if shard.batch.len() == BATCH_SIZE {
+                shard.flush();
+            }
+            return Ok(());
+        };
+
+        // both batches go out straight away, the lending shard waits for the borrowing one and the other way round
+        let (lend, lent) = mpsc::sync_channel(1);
+        let (give_back, returned) = mpsc::sync_channel(1);
+        self.shards[other].batch.push(Job::Lend { client, lent: lend, returned });
+        self.shards[other].flush();
+        let borrow = Borrow { client, lent, returned: give_back };
+        self.shards[index].batch.push(Job::Apply { tag, transaction, owner, clock, borrow: Some(borrow) });
+        self.shards[index].flush();
+        Ok(())
+    }
+
+    // waits for every shard to finish and merges them back into one engine
+    // rejections come back in submission order per client, not across clients
+    pub fn finish(self) -> (Engine, Vec<(T, RejectReason)>) {
+        let mut engine = self.base;
+        let mut rejections = vec![];
+        for mut shard in self.shards {
+            shard.flush();
+            drop(shard.sender);
+
+            let (shard_engine, shard_rejections) = shard.worker.join().unwrap_or_else(|err| panic::resume_unwind(err));
+            engine.absorb(shard_engine);
+            rejections.extend(shard_rejections);
+        }
+        (engine, rejections)
+    }
+}
+
+impl<T> Shard<T> {
+    fn flush(&mut self) {
+        let batch = mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+        // a worker only hangs up by panicking, finish passes that panic on
+        let _ = self.sender.send(batch);
+    }
+}
+
+impl Engine {
+    // runs a job on a shard, returns the rejection if it was a rejected transaction
+    fn run<T>(&mut self, job: Job<T>) -> Option<(T, RejectReason)> {
+        match job {
+            Job::Apply { tag, transaction, owner, clock, borrow } => {
+                if let Some(borrow) = &borrow {
+                    self.absorb(borrow.lent.recv().expect("lending shard stopped"));
+                }
+                self.clock = clock;
+                let result = self.handle_indexed(transaction, owner);
+                if let Some(borrow) = borrow {
+                    let _ = borrow.returned.send(self.take_client(borrow.client));
+                }
+                result.err().map(|reason| (tag, reason))
+            }
+            Job::Lend { client, lent, returned } => {
+                let _ = lent.send(self.take_client(client));
+                self.absorb(returned.recv().expect("borrowing shard stopped"));
+                None
+            }
+        }
+    }
+
+    // moves one client's accounts, transactions and sub-accounts out into an engine of their own
+    fn take_client(&mut self, client: u16) -> Engine {
+        let mut taken = Engine::with_config(self.config.clone());
+        if let Some(account_info) = self.accounts.remove(&client) {
+            taken.accounts.insert(client, account_info);
+        }
+        if let Some(balances) = self.currency_accounts.remove(&client) {
+            taken.currency_accounts.insert(client, balances);
+        }
+        if let Some(statuses) = self.transaction_status.remove(&client) {
+            taken.transaction_status.insert(client, statuses);
+        }
+
+        // a client only has sub-accounts in the currencies it has a balance in
+        let currencies = taken.balances().map(|(_, currency, _)| currency).collect::<Vec<_>>();
+        for currency in currencies {
+            for sub_account in [SubAccount::Available, SubAccount::Held] {
+                let key = (currency, BookAccount::Client(client, sub_account));
+                if let Some(balance) = self.books.balances.remove(&key) {
+                    taken.books.balances.insert(key, balance);
+                }
+            }
+        }
+        taken
+    }
+
+    // moves every client's accounts, transactions and sub-accounts into the shard for client % shards
+    // what stays behind is shared state: config, the tx id index, sequence numbers and the system book accounts
+    fn split(&mut self, shards: usize) -> Vec<Engine> {
+        let mut engines: Vec<Engine> = (0..shards).map(|_| Engine::with_config(self.config.clone())).collect();
+        let shard = |client: u16| usize::from(client) % shards;
+
+        for (client, account_info) in self.accounts.drain() {
+            engines[shard(client)].accounts.insert(client, account_info);
+        }
+        for (client, balances) in self.currency_accounts.drain() {
+            engines[shard(client)].currency_accounts.insert(client, balances);
+        }
+        for (client, statuses) in self.transaction_status.drain() {
+            engines[shard(client)].transaction_status.insert(client, statuses);
+        }
+        self.books.balances.retain(|key, balance| match key.1 {
+            BookAccount::Client(client, _) => {
+                engines[shard(client)].books.balances.insert(*key, *balance);
+                false
+            }
+            _ => true,
+        });
+        engines
+    }
+
+    // takes a shard back in, shards count their ledger events from 0 and start their system book accounts at 0
+    fn absorb(&mut self, shard: Engine) {
+        self.clock = self.clock.max(shard.clock);
+        self.accounts.extend(shard.accounts);
+        self.currency_accounts.extend(shard.currency_accounts);
+        self.transaction_status.extend(shard.transaction_status);
+        self.ledger_seq += shard.ledger_seq;
+
+        for ((currency, account), balance) in shard.books.balances {
+            // client sub-accounts are only in one shard, system accounts are summed
+            // a system account would have to pass 922 trillion to overflow, no shard can check that for the others
+            let total = self.books.balance_in(currency, account).checked_add(balance).expect("system book account overflowed");
+            self.books.balances.insert((currency, account), total);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::engine::TxError;
+    use crate::test_support::transaction;
+
+    // a mixed workload with every kind of rejection, spread over more clients than shards
+    fn workload() -> Vec<Transaction> {
+        let mut transactions = vec![];
+        for client in 1..=20u16 {
+            let id = u32::from(client) * 100;
+            transactions.push(transaction("deposit", client, id, "10.0"));
+            transactions.push(transaction("Withdrawal", client, id + 1, "2.5"));
+            transactions.push(transaction("withdrawal", client, id + 2, "100.0"));
+            transactions.push(transaction("dispute", client, id + 1, ""));
+            transactions.push(transaction("dispute", client % 20 + 1, id, ""));
+            transactions.push(transaction("deposit", client % 20 + 1, id, "1.0"));
+            if client % 3 == 0 {
+                transactions.push(transaction("dispute", client, id, ""));
+                transactions.push(transaction("chargeback", client, id, ""));
+                transactions.push(transaction("deposit", client, id + 3, "1.0"));
+            } else {
+                transactions.push(transaction("resolve", client, id + 1, ""));
+                transactions.push(transaction("deposit", client, id + 3, "1.0"));
+            }
+            if client % 4 == 0 {
+                transactions.push(Transaction { currency: "EUR".parse().ok(), ..transaction("deposit", client, id + 4, "2.0") });
+                transactions.push(transaction("dispute", client, id + 4, ""));
+            }
+            // transfers to the next client, in another shard unless there is only one
+            transactions.push(Transaction { destination: Some(client % 20 + 1), ..transaction("transfer", client, id + 5, "0.5") });
+            if client % 5 == 0 {
+                transactions.push(transaction("dispute", client, id + 5, ""));
+                let settle = if client % 10 == 0 { "chargeback" } else { "resolve" };
+                transactions.push(transaction(settle, client, id + 5, ""));
+            }
+        }
+        transactions
+    }
+
+    fn sequential(transactions: &[Transaction]) -> (Engine, Vec<(usize, RejectReason)>) {
+        let mut engine = Engine::new();
+        let mut rejections = vec![];
+        for (index, transaction) in transactions.iter().enumerate() {
+            if let Err(TxError::Rejected(reason)) = engine.apply(transaction.clone()) {
+                rejections.push((index, reason));
+            }
+        }
+        (engine, rejections)
+    }
+
+    fn sharded(engine: Engine, shards: usize, transactions: &[Transaction]) -> (Engine, Vec<(usize, RejectReason)>) {
+        let mut sharded = ShardedEngine::new(engine, shards);
+        let mut rejections = vec![];
+        for (index, transaction) in transactions.iter().enumerate() {
+            if let Err(rejection) = sharded.submit(index, transaction.clone()) {
+                rejections.push(rejection);
+            }
+        }
+        let (engine, shard_rejections) = sharded.finish();
+        rejections.extend(shard_rejections);
+        rejections.sort_by_key(|(index, _)| *index);
+        (engine, rejections)
+    }
+
+    #[test]
+    fn test_sharded_matches_sequential() {
+        let transactions = workload();
+        let (expected, expected_rejections) = sequential(&transactions);
+
+        for shards in [1, 3, 8] {
+            let (engine, rejections) = sharded(Engine::new(), shards, &transactions);
+
+            assert_eq!(engine.accounts, expected.accounts, "{} shards", shards);
+            assert_eq!(engine.currency_accounts, expected.currency_accounts);
+            assert_eq!(engine.transaction_status, expected.transaction_status);
+            assert_eq!(engine.tx_clients, expected.tx_clients);
+            assert_eq!(engine.books, expected.books);
+            assert_eq!(engine.ledger_seq, expected.ledger_seq);
+            assert_eq!(rejections, expected_rejections);
+        }
+    }
+
+    #[test]
+    fn test_sharded_transfers_across_shards_in_both_directions() {
+        // clients 1 and 2 are in different shards and send money back and forth, each transfer needs the one before it
+        let mut transactions = vec![transaction("deposit", 1, 1, "1.0")];
+        for id in 2..2000 {
+            let (from, to) = if id % 2 == 0 { (1, 2) } else { (2, 1) };
+            transactions.push(Transaction { destination: Some(to), ..transaction("transfer", from, id, "1.0") });
+            transactions.push(transaction("deposit", (id % 5 + 3) as u16, id + 10_000, "1.0"));
+        }
+
+        let (engine, rejections) = sharded(Engine::new(), 4, &transactions);
+        assert!(rejections.is_empty());
+        assert_eq!(engine.account(1).unwrap().available, "1.0".parse().unwrap());
+        assert_eq!(engine.account(2).unwrap().available, Amount::ZERO);
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_sharded_resumes_and_disputes_transfers_from_the_snapshot() {
+        // the transfers were applied before the snapshot, their receivers are in other shards than the sender
+        let before = [
+            transaction("deposit", 1, 1, "10.0"),
+            Transaction { destination: Some(2), ..transaction("transfer", 1, 2, "4.0") },
+            Transaction { destination: Some(3), ..transaction("transfer", 1, 3, "1.0") },
+        ];
+        let after = [transaction("dispute", 1, 2, ""), transaction("dispute", 1, 3, ""), transaction("chargeback", 1, 2, "")];
+        let (expected, _) = sequential(&[before.to_vec(), after.to_vec()].concat());
+
+        let mut snapshot = vec![];
+        sequential(&before).0.save_snapshot(&mut snapshot).unwrap();
+        let resumed = Engine::load_snapshot(snapshot.as_slice(), Default::default()).unwrap();
+        let (engine, rejections) = sharded(resumed, 4, &after);
+
+        assert!(rejections.is_empty());
+        assert_eq!(engine.accounts, expected.accounts);
+        assert_eq!(engine.account(2).unwrap().total, Amount::ZERO);
+        assert_eq!(engine.account(3).unwrap().held, "1.0".parse().unwrap());
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+
+    #[test]
+    fn test_sharded_keeps_per_client_order() {
+        let mut transactions = vec![transaction("deposit", 1, 1, "1.0")];
+        for id in 2..5000 {
+            transactions.push(transaction("deposit", (id % 3 + 2) as u16, id, "1.0"));
+            transactions.push(transaction("withdrawal", 1, id + 10_000, "1.0"));
+            transactions.push(transaction("deposit", 1, id + 20_000, "1.0"));
+        }
+
+        // every withdrawal needs the deposit before it, out of order one would be rejected
+        let (engine, rejections) = sharded(Engine::new(), 4, &transactions);
+        assert!(rejections.is_empty());
+        assert_eq!(engine.account(1).unwrap().available, "1.0".parse().unwrap());
+    }
+
+    #[test]
+    fn test_sharded_rejected_rows_leave_the_clock_alone() {
+        let at = |timestamp: u64, transaction: Transaction| Transaction { timestamp: Some(timestamp), ..transaction };
+        let transactions = [
+            at(10, transaction("deposit", 1, 1, "1.0")),
+            at(1_000, transaction("deposit", 2, 1, "1.0")),
+            at(1_000, transaction("withdrawal", 1, 2, "-1.0")),
+            at(1_000, transaction("dispute", 2, 1, "")),
+        ];
+
+        let (engine, rejections) = sharded(Engine::new(), 2, &transactions);
+        assert_eq!(rejections.iter().map(|(_, reason)| *reason).collect::<Vec<_>>(), [RejectReason::DuplicateTransaction, RejectReason::InvalidAmount, RejectReason::ClientMismatch]);
+        assert_eq!(engine.clock(), Some(10));
+    }
+
+    #[test]
+    fn test_sharded_continues_from_engine() {
+        let transactions = workload();
+        let (half, _) = sequential(&transactions[..70]);
+        let (expected, _) = sequential(&transactions);
+
+        let (engine, _) = sharded(half, 3, &transactions[70..]);
+
+        assert_eq!(engine.accounts, expected.accounts);
+        assert_eq!(engine.books, expected.books);
+        assert_eq!(engine.check_books(), Ok(()));
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::io::{Read, Seek};
+
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+
+use crate::currency::ParseCurrencyError;
+use crate::transaction::{LenientTransaction, Transaction};
+
+// how malformed fields are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    // malformed fields fall back to their default values, as the engine always did
+    // client 0 and tx 0 are what malformed ids default to, so rows with them are never applied
+    #[default]
+    Lenient,
+    // a malformed field is a parse error, client 0 and tx 0 are ordinary ids
+    Strict,
+}
+
+// a single input row, kept raw alongside the parsed transaction so rejected rows can be reported as they were read
+#[derive(Debug)]
+pub struct Row {
+    // 1-based line in the input, the header is line 1
+    pub line: u64,
+    pub record: StringRecord,
+    pub transaction: Result<Transaction, ParseError>,
+    // lenient mode only, why the strict parse failed for a row whose fields were defaulted
+    pub defaulted: Option<ParseError>,
+    // where the row after this one starts, reading can be resumed from here
+    pub next: InputPosition,
+}
+
+// a place in the input, mirrors csv::Position so it can be stored in the journal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputPosition {
+    pub byte: u64,
+    pub line: u64,
+    pub record: u64,
+}
+
+impl From<&csv::Position> for InputPosition {
+    fn from(position: &csv::Position) -> InputPosition {
+        InputPosition { byte: position.byte(), line: position.line(), record: position.record() }
+    }
+}
+
+// a row that could not be turned into a Transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: u64,
+    // 1-based field the error is in, None when the row as a whole is malformed
+    pub column: Option<u64>,
+    pub message: String,
+}
+
+impl ParseError {
+    fn from_csv(line: u64, err: csv::Error, amount_column: Option<u64>, currency_column: Option<u64>) -> ParseError {
+        // csv knows the field for errors it raises itself (numbers, missing fields) but not for custom errors
+        // custom errors come from parsing an Amount or a Currency, currency errors are told apart by their message
+        match err.kind() {
+            csv::ErrorKind::Deserialize { err: de_err, .. } => ParseError {
+                line,
+                column: de_err.field().map(|field| field + 1).or(match de_err.kind() {
+                    csv::DeserializeErrorKind::Message(message) if message == &ParseCurrencyError.to_string() => currency_column,
+                    csv::DeserializeErrorKind::Message(_) => amount_column,
+                    _ => None,
+                }),
+                message: de_err.kind().to_string(),
+            },
+            _ => ParseError { line, column: None, message: err.to_string() },
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.column {
+            Some(column) => write!(f, "line {}, column {}: {}", self.line, column, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// turns csv records into transactions, for records that do not come from a TransactionReader
+pub struct RecordParser {
+    headers: StringRecord,
+    // 1-based positions of the amount and currency headers
+    amount_column: Option<u64>,
+    currency_column: Option<u64>,
+    mode: ParseMode,
+}
+
+impl RecordParser {
+    pub fn new(headers: StringRecord, mode: ParseMode) -> RecordParser {
+        let column = |name: &str| headers.iter().position(|header| header == name).map(|index| index as u64 + 1);
+        let amount_column = column("amount");
+        let currency_column = column("currency");
+        RecordParser { headers, amount_column, currency_column, mode }
+    }
+
+    // returns the transaction, and in lenient mode why the strict parse failed for a row whose fields were defaulted
+    pub fn parse(&self, line: u64, record: &StringRecord) -> (Result<Transaction, ParseError>, Option<ParseError>) {
+        if record.len() != self.headers.len() {
+            let err = ParseError {
+                line,
+                column: None,
+                message: format!("expected {} fields, found {}", self.headers.len(), record.len()),
+            };
+            return (Err(err), None);
+        }
+
+        let strict = record.deserialize::<Transaction>(Some(&self.headers)).map_err(|err| ParseError::from_csv(line, err, self.amount_column, self.currency_column));
+        if self.mode == ParseMode::Strict {
+            return (strict, None);
+        }
+
+        // lenient keeps the strict error around to explain the defaults
+        let (transaction, defaulted) = match strict {
+            Ok(transaction) => (transaction, None),
+            Err(err) => match record.deserialize::<LenientTransaction>(Some(&self.headers)) {
+                Ok(lenient) => (Transaction::from(lenient), Some(err)),
+                Err(_) => return (Err(err), None),
+            },
+        };
+
+        if transaction.client == 0 || transaction.id == 0 {
+            let err = defaulted.unwrap_or_else(|| ParseError {
+                line,
+                column: None,
+                message: "client and tx must be greater than 0".to_string(),
+            });
+            return (Err(err), None);
+        }
+
+        (Ok(transaction), defaulted)
+    }
+}
+
+// streams rows from a csv source one at a time
+// rows with a different number of fields than the header are not fatal, they come back with a parse error
+pub struct TransactionReader<R> {
+    parser: RecordParser,
+    rdr: csv::Reader<R>,
+}
+
+impl<R: Read> TransactionReader<R> {
+    pub fn new(input: R, mode: ParseMode) -> csv::Result<TransactionReader<R>> {
+        let mut rdr = csv::ReaderBuilder::new().flexible(true).from_reader(input);
+        let headers = rdr.headers()?.clone();
+        Ok(TransactionReader { parser: RecordParser::new(headers, mode), rdr })
+    }
+}
+
+impl<R: Read + Seek> TransactionReader<R> {
+    // continues reading at a position taken from Row::next, the header has already been read
+    pub fn seek(&mut self, position: InputPosition) -> csv::Result<()> {
+        let mut pos = csv::Position::new();
+        pos.set_byte(position.byte).set_line(position.line).set_record(position.record);
+        self.rdr.seek(pos)
+    }
+}
+
+impl<R: Read> Iterator for TransactionReader<R> {
+    // io errors and invalid utf-8 are fatal and end the stream
+    type Item = csv::Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut record = StringRecord::new();
+        match self.rdr.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(err) => return Some(Err(err)),
+        }
+
+        let line = record.position().map_or(0, |position| position.line());
+        let (transaction, defaulted) = self.parser.parse(line, &record);
+        let next = InputPosition::from(self.rdr.position());
+
+        Some(Ok(Row { line, record, transaction, defaulted, next }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(input: &str, mode: ParseMode) -> Vec<Row> {
+        TransactionReader::new(input.as_bytes(), mode).unwrap().map(|row| row.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_reader_keeps_line_numbers_and_raw_records() {
+        let rows = read("type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,abc,2,1.0\n", ParseMode::Lenient);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].line, 2);
+        assert_eq!(rows[0].transaction.as_ref().unwrap().client, 1);
+        assert_eq!(rows[1].line, 3);
+        assert_eq!(&rows[1].record[1], "abc");
+    }
+
+    #[test]
+    fn test_reader_wrong_field_count_is_not_fatal() {
+        let rows = read("type,client,tx,amount\ndeposit,1\ndeposit,1,2,1.0\n", ParseMode::Lenient);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].transaction.as_ref().unwrap_err().to_string(), "line 2: expected 4 fields, found 2");
+        assert_eq!(rows[1].transaction.as_ref().unwrap().id, 2);
+    }
+
+    #[test]
+    fn test_reader_lenient_defaults_malformed_fields() {
+        let rows = read("type,client,tx,amount\ndeposit,1,1,1.23456\ndispute,1,1,\n", ParseMode::Lenient);
+
+        // amount defaulted, the strict error is kept to explain why
+        let transaction = rows[0].transaction.as_ref().unwrap();
+        assert_eq!(transaction.amount, None);
+        let defaulted = rows[0].defaulted.as_ref().unwrap();
+        assert_eq!(defaulted.line, 2);
+        assert_eq!(defaulted.column, Some(4));
+
+        // an empty amount is not malformed
+        assert_eq!(rows[1].transaction.as_ref().unwrap().amount, None);
+        assert!(rows[1].defaulted.is_none());
+    }
+
+    #[test]
+    fn test_reader_lenient_rejects_client_and_tx_zero() {
+        let rows = read("type,client,tx,amount\ndeposit,abc,1,1.0\ndeposit,0,2,1.0\ndeposit,1,0,1.0\n", ParseMode::Lenient);
+
+        assert_eq!(rows[0].transaction.as_ref().unwrap_err().column, Some(2));
+        assert_eq!(rows[1].transaction.as_ref().unwrap_err().message, "client and tx must be greater than 0");
+        assert!(rows[2].transaction.is_err());
+    }
+
+    #[test]
+    fn test_reader_strict_surfaces_parse_errors() {
+        let rows = read("type,client,tx,amount\ndeposit,1,x,1.0\nwithdrawal,1,2,1.23456\n", ParseMode::Strict);
+
+        let err = rows[0].transaction.as_ref().unwrap_err();
+        assert_eq!((err.line, err.column), (2, Some(3)));
+        assert!(err.to_string().starts_with("line 2, column 3: "));
+
+        let err = rows[1].transaction.as_ref().unwrap_err();
+        assert_eq!((err.line, err.column), (3, Some(4)));
+        assert!(err.message.contains("more than 4 decimal places"));
+    }
+
+    #[test]
+    fn test_reader_optional_currency_column() {
+        let input = "type,client,tx,amount,currency\ndeposit,1,1,1.0,eur\ndeposit,1,2,1.0,\ndeposit,1,3,1.0,euro\n";
+        let rows = read(input, ParseMode::Lenient);
+
+        assert_eq!(rows[0].transaction.as_ref().unwrap().currency, "EUR".parse().ok());
+        assert_eq!(rows[1].transaction.as_ref().unwrap().currency, None);
+        // a malformed currency is never defaulted, even in lenient mode
+        let err = rows[2].transaction.as_ref().unwrap_err();
+        assert_eq!((err.line, err.column), (4, Some(5)));
+    }
+
+    #[test]
+    fn test_reader_seek_resumes_at_next_row() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\ndeposit,1,3,1.0\n";
+        let first = read(input, ParseMode::Lenient).remove(0);
+
+        let mut reader = TransactionReader::new(std::io::Cursor::new(input), ParseMode::Lenient).unwrap();
+        reader.seek(first.next).unwrap();
+        let rest: Vec<Row> = reader.map(|row| row.unwrap()).collect();
+
+        assert_eq!(rest.len(), 2);
+        assert_eq!(rest[0].line, 3);
+        assert_eq!(rest[0].transaction.as_ref().unwrap().id, 2);
+    }
+
+    #[test]
+    fn test_reader_strict_accepts_client_and_tx_zero() {
+        let rows = read("type,client,tx,amount\ndeposit,0,0,1.0\n", ParseMode::Strict);
+
+        let transaction = rows[0].transaction.as_ref().unwrap();
+        assert_eq!((transaction.client, transaction.id), (0, 0));
+    }
+}
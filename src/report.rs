@@ -0,0 +1,69 @@
+use std::io::Write;
+
+use csv::StringRecord;
+
+// csv report of input rows that did nothing
+// columns: line, record (the raw input row), reason (snake_case code), detail (parse error message, if any)
+pub struct RejectedReport<W: Write> {
+    wtr: csv::Writer<W>,
+}
+
+impl<W: Write> RejectedReport<W> {
+    pub fn new(output: W) -> csv::Result<RejectedReport<W>> {
+        let mut wtr = csv::Writer::from_writer(output);
+        wtr.write_record(["line", "record", "reason", "detail"])?;
+        Ok(RejectedReport { wtr })
+    }
+
+    // continues a report that already has its header, e.g. one a crashed run was writing
+    pub fn appending(output: W) -> RejectedReport<W> {
+        RejectedReport { wtr: csv::Writer::from_writer(output) }
+    }
+
+    pub fn write(&mut self, line: u64, record: &StringRecord, reason: &str, detail: &str) -> csv::Result<()> {
+        let raw = raw_record(record)?;
+        self.wtr.write_record([line.to_string().as_str(), raw.as_str(), reason, detail])
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.wtr.flush()
+    }
+}
+
+fn raw_record(record: &StringRecord) -> csv::Result<String> {
+    // re-encode the row as a single csv line so fields with commas or quotes survive
+    let mut wtr = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    wtr.write_record(record)?;
+    let mut raw = String::from_utf8(wtr.into_inner().map_err(|err| err.into_error())?)
+        .expect("csv writer only writes the utf-8 it was given");
+    raw.pop();
+    Ok(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_writes_header_and_rows() {
+        let mut report = RejectedReport::new(vec![]).unwrap();
+        report.write(2, &StringRecord::from(vec!["withdrawal", "1", "2", "5.0"]), "insufficient_funds", "").unwrap();
+        report.write(3, &StringRecord::from(vec!["deposit", "1"]), "malformed", "expected 4 fields, found 2").unwrap();
+
+        let output = String::from_utf8(report.wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(output, "line,record,reason,detail\n\
+                            2,\"withdrawal,1,2,5.0\",insufficient_funds,\n\
+                            3,\"deposit,1\",malformed,\"expected 4 fields, found 2\"\n");
+    }
+
+    #[test]
+    fn test_report_appending_has_no_header() {
+        let mut report = RejectedReport::appending(vec![]);
+        report.write(7, &StringRecord::from(vec!["dispute", "1", "9", ""]), "unknown_transaction", "").unwrap();
+
+        let output = String::from_utf8(report.wtr.into_inner().unwrap()).unwrap();
+        assert_eq!(output, "7,\"dispute,1,9,\",unknown_transaction,\n");
+    }
+}
@@ -0,0 +1,246 @@
+use std::io;
+use std::net::SocketAddr;
+
+use csv::StringRecord;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::engine::TxError;
+use crate::handle::EngineHandle;
+use crate::output::{sorted_accounts, write_accounts, OutputFormat, SortOrder};
+use crate::reader::{ParseMode, RecordParser};
+
+// lines are read with these columns until the producer sends a header line of its own
+// currency, destination, reason, timestamp and dispute are optional and a line can leave them out from the end
+const HEADERS: [&str; 9] = ["type", "client", "tx", "amount", "currency", "destination", "reason", "timestamp", "dispute"];
+
+// a line can leave out the columns after the first four
+const REQUIRED_COLUMNS: usize = 4;
+
+// one parser per number of columns a line can have, from the required ones up to every column of the header
+struct Columns {
+    required: usize,
+    parsers: Vec<RecordParser>,
+}
+
+impl Columns {
+    fn new(headers: &StringRecord, mode: ParseMode) -> Columns {
+        let required = REQUIRED_COLUMNS.min(headers.len());
+        let parsers = (required..=headers.len()).map(|len| RecordParser::new(headers.iter().take(len).collect(), mode)).collect();
+        Columns { required, parsers }
+    }
+
+    // lines with too few or too many columns get the full parser, which reports them as malformed
+    fn parser(&self, len: usize) -> &RecordParser {
+        len.checked_sub(self.required).and_then(|i| self.parsers.get(i)).unwrap_or(&self.parsers[self.parsers.len() - 1])
+    }
+}
+
+// a line of nothing but column names is a header, the lines after it map their columns by its names like a csv file does
+fn is_header(record: &StringRecord) -> bool {
+    record.iter().any(|field| field == "type") && record.iter().all(|field| HEADERS.contains(&field))
+}
+
+// accepts connections until the listener fails
+// each connection sends one csv record per line, or DUMP to get the accounts back as csv followed by an empty line
+// the engine applies the records of all connections in the order they arrive, so the records a connection sends
+// are applied in the order it sent them and a DUMP sees every record sent before it on the same connection
+// rejected and malformed records are logged to stderr, nothing is sent back for them
+pub async fn serve(listener: TcpListener, engine: EngineHandle, mode: ParseMode) -> io::Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let engine = engine.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, peer, engine, mode).await {
+                eprintln!("{}: {}", peer, err);
+            }
+        });
+    }
+}
+
+async fn handle_connection(stream: TcpStream, peer: SocketAddr, engine: EngineHandle, mode: ParseMode) -> io::Result<()> {
+    let mut columns = Columns::new(&StringRecord::from(HEADERS.to_vec()), mode);
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut line_number = 0;
+
+    while let Some(line) = lines.next_line().await? {
+        line_number += 1;
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.eq_ignore_ascii_case("dump") {
+            // the same csv the cli writes, the empty line ends it
+            let accounts = engine
+                .call(|engine| {
+                    let mut output = vec![];
+                    let accounts = sorted_accounts(engine, SortOrder::Client);
+                    // writing to a Vec cannot fail
+                    let _ = write_accounts(&mut output, &accounts, OutputFormat::Csv);
+                    output.push(b'\n');
+                    output
+                })
+                .await?;
+            writer.write_all(&accounts).await?;
+            continue;
+        }
+
+        let record = match parse_record(line) {
+            Ok(record) => record,
+            Err(err) => {
+                eprintln!("{} line {}: {}", peer, line_number, err);
+                continue;
+            }
+        };
+        if is_header(&record) {
+            columns = Columns::new(&record, mode);
+            continue;
+        }
+
+        match columns.parser(record.len()).parse(line_number, &record).0 {
+            Ok(transaction) => {
+                engine
+                    .send(move |engine| {
+                        if let Err(TxError::Rejected(reason)) = engine.apply(transaction) {
+                            eprintln!("{} line {}: {}", peer, line_number, reason);
+                        }
+                    })
+                    .await?
+            }
+            Err(err) => eprintln!("{}: {}", peer, err),
+        }
+    }
+    Ok(())
+}
+
+fn parse_record(line: &str) -> csv::Result<StringRecord> {
+    let mut rdr = csv::ReaderBuilder::new().has_headers(false).flexible(true).from_reader(line.as_bytes());
+    let mut record = StringRecord::new();
+    rdr.read_record(&mut record)?;
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpStream;
+
+    async fn start() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, EngineHandle::spawn(Engine::new()), ParseMode::Lenient));
+        addr
+    }
+
+    // sends DUMP and reads the reply up to the empty line that ends it
+    async fn dump(stream: &mut TcpStream) -> Vec<String> {
+        stream.write_all(b"DUMP\n").await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let mut accounts = vec![];
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if line.is_empty() {
+                break;
+            }
+            accounts.push(line);
+        }
+        accounts
+    }
+
+    #[tokio::test]
+    async fn test_server_applies_records_and_dumps_accounts() {
+        let addr = start().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        stream
+            .write_all(b"type,client,tx,amount\r\ndeposit,1,1,10.0\r\nwithdrawal,1,2,2.5\nnot,a,record\ndispute,1,1,\n")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            dump(&mut stream).await,
+            vec!["client,available,held,total,locked", "1,-2.5000,10.0000,7.5000,false"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_applies_transfers() {
+        let addr = start().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        stream
+            .write_all(b"type,client,tx,amount,currency,destination
+deposit,1,1,10.0
+transfer,1,2,4.0,,2
+transfer,1,3,1.0,,1
+")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            dump(&mut stream).await,
+            vec!["client,available,held,total,locked", "1,6.0000,0.0000,6.0000,false", "2,4.0000,0.0000,4.0000,false"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_maps_columns_by_the_header_it_receives() {
+        let addr = start().await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // without the header, 2 would be read as the currency of the transfer
+        stream
+            .write_all(b"deposit,1,1,10.0
+type,client,tx,amount,destination
+transfer,1,2,4.0,2
+withdrawal,1,3,1.0
+")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            dump(&mut stream).await,
+            vec!["client,available,held,total,locked", "1,5.0000,0.0000,5.0000,false", "2,4.0000,0.0000,4.0000,false"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_keeps_order_per_connection_across_producers() {
+        let addr = start().await;
+
+        // every withdrawal needs the deposit sent just before it on the same connection
+        let producers: Vec<_> = (1..=4u16)
+            .map(|client| {
+                tokio::spawn(async move {
+                    let mut stream = TcpStream::connect(addr).await.unwrap();
+                    let mut records = String::new();
+                    for n in 0..500u32 {
+                        let id = u32::from(client) * 10_000 + n * 2;
+                        records.push_str(&format!("deposit,{},{},1.0\nwithdrawal,{},{},1.0\n", client, id, client, id + 1));
+                    }
+                    records.push_str(&format!("deposit,{},{},{}.0\n", client, u32::from(client) * 10_000 + 9_999, client));
+                    stream.write_all(records.as_bytes()).await.unwrap();
+                    dump(&mut stream).await
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.await.unwrap();
+        }
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(
+            dump(&mut stream).await,
+            vec![
+                "client,available,held,total,locked",
+                "1,1.0000,0.0000,1.0000,false",
+                "2,2.0000,0.0000,2.0000,false",
+                "3,3.0000,0.0000,3.0000,false",
+                "4,4.0000,0.0000,4.0000,false",
+            ]
+        );
+    }
+}
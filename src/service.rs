@@ -0,0 +1,100 @@
+use std::io;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+use crate::config::EngineConfig;
+use crate::engine::Engine;
+use crate::handle::EngineHandle;
+use crate::reader::ParseMode;
+use crate::snapshot::SnapshotError;
+
+// command line options of the tcp server and the api, the same meaning as for the csv binary
+// mode is only ever strict for a service that reads rows, the api takes json and has no --strict
+pub struct ServiceOptions {
+    pub address: String,
+    pub mode: ParseMode,
+    pub config: EngineConfig,
+    pub resume_from: Option<String>,
+}
+
+impl ServiceOptions {
+    // parses the arguments after the program name, the address is the one argument that is not a flag
+    pub fn parse(args: &[String], default_address: &str, strict: bool) -> Result<ServiceOptions, String> {
+        let mut address = None;
+        let mut mode = ParseMode::Lenient;
+        let mut config = EngineConfig::default();
+        let mut resume_from = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if config.parse_flag(arg, &mut args)? {
+                continue;
+            }
+
+            match arg.as_str() {
+                "--strict" if strict => mode = ParseMode::Strict,
+                "--resume-from" => {
+                    let path = args.next().ok_or("--resume-from needs a snapshot file path")?;
+                    resume_from = Some(path.clone());
+                }
+                _ if address.is_none() && !arg.starts_with("--") => address = Some(arg.clone()),
+                _ => return Err(format!("unexpected argument '{}'", arg)),
+            }
+        }
+
+        let address = address.unwrap_or_else(|| default_address.to_string());
+        Ok(ServiceOptions { address, mode, config, resume_from })
+    }
+
+    // the engine the service runs, resumed from the snapshot if there is one
+    // deadlines are settled on the wall clock as well as on row timestamps, rows may not come often enough
+    pub fn start_engine(&self) -> Result<EngineHandle, SnapshotError> {
+        let config = self.config.clone();
+        let engine = match &self.resume_from {
+            Some(path) => Engine::load_snapshot_file(path, config)?,
+            None => Engine::with_config(config),
+        };
+
+        let engine = EngineHandle::spawn(engine);
+        if self.config.resolve_within.is_some() {
+            engine.drive_clock(Duration::from_secs(1));
+        }
+        Ok(engine)
+    }
+
+    // binds the address and says where the service listens, the port can be 0 for any free one
+    pub async fn listen(&self) -> io::Result<TcpListener> {
+        let listener = TcpListener::bind(&self.address).await?;
+        eprintln!("listening on {}", listener.local_addr()?);
+        Ok(listener)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_address_flags_and_resume_from() {
+        let options = ServiceOptions::parse(&args(&["0.0.0.0:9000", "--strict", "--resume-from", "state.json", "--resolve-within", "1h"]), "127.0.0.1:1", true).unwrap();
+        assert_eq!(options.address, "0.0.0.0:9000");
+        assert_eq!(options.mode, ParseMode::Strict);
+        assert_eq!(options.resume_from.as_deref(), Some("state.json"));
+        assert_eq!(options.config.resolve_within, Some(3_600));
+
+        let options = ServiceOptions::parse(&[], "127.0.0.1:1", false).unwrap();
+        assert_eq!((options.address.as_str(), options.mode, options.resume_from), ("127.0.0.1:1", ParseMode::Lenient, None));
+    }
+
+    #[test]
+    fn test_parse_rejects_strict_where_it_is_not_taken() {
+        assert!(ServiceOptions::parse(&args(&["--strict"]), "127.0.0.1:1", false).is_err());
+        assert!(ServiceOptions::parse(&args(&["--resume-from"]), "127.0.0.1:1", true).is_err());
+        assert!(ServiceOptions::parse(&args(&["127.0.0.1:2", "127.0.0.1:3"]), "127.0.0.1:1", true).is_err());
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use serde::de::Error as _;
+use serde::{Deserialize, Serialize};
+
+use crate::account::AccountInfo;
+use crate::amount::Amount;
+use crate::books::{BookAccount, Books};
+use crate::config::EngineConfig;
+use crate::currency::Currency;
+use crate::engine::Engine;
+use crate::transaction::{Dispute, TransactionStatus};
+
+// bumped whenever the snapshot layout changes, newer versions are refused rather than misread
+// older versions are migrated as they are loaded, see migrate_v1 and migrate_v2, version 3 loads as it is
+// 1: disputes and chargebacks as flags of the whole tx, the fields with serde(default) can be missing from it
+// 2: every open dispute with its amount and deadline, and the amount charged back
+// 3: every open dispute with its id, and how many disputes each tx has had
+// 4: the amount resolved so far, older versions start from nothing resolved
+pub const SNAPSHOT_VERSION: u32 = 4;
+
+// full engine state as json
+// BTreeMaps keep the file sorted, so the same state always gives the same file
+// config is not part of the state, it comes from whoever loads the snapshot
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    // key: client
+    accounts: BTreeMap<u16, AccountInfo>,
+    // balances in named currencies, missing from snapshots taken before the engine had them
+    // key: client, then currency
+    #[serde(default)]
+    currency_accounts: BTreeMap<u16, BTreeMap<Currency, AccountInfo>>,
+    // key: client, then tx
+    transactions: BTreeMap<u16, BTreeMap<u32, TransactionStatus>>,
+    // key: tx, value: client
+    tx_clients: BTreeMap<u32, u16>,
+    // last journal entry included in this snapshot, journal entries up to here are not replayed
+    #[serde(default)]
+    journal_seq: u64,
+    // last ledger event emitted, the events themselves are not part of the snapshot
+    #[serde(default)]
+    ledger_seq: u64,
+    // balance of every book account in the default currency, missing from snapshots taken before the engine kept books
+    #[serde(default)]
+    books: Option<Vec<(BookAccount, Amount)>>,
+    // balance of every book account in a named currency
+    #[serde(default)]
+    currency_books: Vec<(Currency, BookAccount, Amount)>,
+    // the engine's clock, missing from snapshots taken before transactions had timestamps
+    #[serde(default)]
+    clock: Option<u64>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    UnsupportedVersion(u32),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "snapshot io error: {}", err),
+            SnapshotError::Format(err) => write!(f, "snapshot is not valid: {}", err),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "snapshot version {} is not supported, expected 1 to {}", version, SNAPSHOT_VERSION)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> SnapshotError {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(err: serde_json::Error) -> SnapshotError {
+        SnapshotError::Format(err)
+    }
+}
+
+// only the version field, read first so a newer layout is reported as such instead of as a format error
+#[derive(Deserialize)]
+struct SnapshotVersion {
+    version: u32,
+}
+
+// what version 1 kept on the tx and later versions keep on its disputes, read next to the snapshot itself
+#[derive(Deserialize)]
+struct LegacyTransactions {
+    // key: client, then tx
+    transactions: BTreeMap<u16, BTreeMap<u32, LegacyStatus>>,
+}
+
+#[derive(Deserialize)]
+struct LegacyStatus {
+    // the deadline of the open dispute, missing from snapshots taken before resolution deadlines
+    #[serde(default)]
+    dispute_deadline: Option<u64>,
+}
+
+impl Engine {
+    pub fn save_snapshot<W: Write>(&self, output: W) -> Result<(), SnapshotError> {
+        let snapshot = Snapshot {
+            version: SNAPSHOT_VERSION,
+            accounts: self.accounts.iter().map(|(client, account_info)| (*client, account_info.clone())).collect(),
+            currency_accounts: self
+                .currency_accounts
+                .iter()
+                .map(|(client, balances)| {
+                    (*client, balances.iter().map(|(currency, account_info)| (*currency, account_info.clone())).collect())
+                })
+                .collect(),
+            transactions: self
+                .transaction_status
+                .iter()
+                .map(|(client, statuses)| {
+                    (*client, statuses.iter().map(|(trans_id, status)| (*trans_id, status.clone())).collect())
+                })
+                .collect(),
+            tx_clients: self.tx_clients.iter().map(|(trans_id, client)| (*trans_id, *client)).collect(),
+            journal_seq: self.journal_seq,
+            ledger_seq: self.ledger_seq,
+            books: Some(
+                self.books
+                    .trial_balance()
+                    .filter(|(currency, _, _)| currency.is_none())
+                    .map(|(_, account, balance)| (account, balance))
+                    .collect(),
+            ),
+            currency_books: self
+                .books
+                .trial_balance()
+                .filter_map(|(currency, account, balance)| currency.map(|currency| (currency, account, balance)))
+                .collect(),
+            clock: self.clock,
+        };
+
+        let mut output = output;
+        serde_json::to_writer(&mut output, &snapshot)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    pub fn load_snapshot<R: Read>(input: R, config: EngineConfig) -> Result<Engine, SnapshotError> {
+        let value: serde_json::Value = serde_json::from_reader(input)?;
+        let version = SnapshotVersion::deserialize(&value)?.version;
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+
+        let legacy = if version == 1 { Some(LegacyTransactions::deserialize(&value)?) } else { None };
+        let snapshot = Snapshot::deserialize(value)?;
+        let mut engine = Engine::with_config(config);
+        engine.accounts = snapshot.accounts.into_iter().collect();
+        engine.currency_accounts = snapshot
+            .currency_accounts
+            .into_iter()
+            .map(|(client, balances)| (client, balances.into_iter().collect()))
+            .collect();
+        engine.transaction_status = snapshot
+            .transactions
+            .into_iter()
+            .map(|(client, statuses)| (client, statuses.into_iter().collect()))
+            .collect();
+        engine.tx_clients = snapshot.tx_clients.into_iter().collect();
+        match (version, legacy) {
+            (1, Some(legacy)) => Engine::migrate_v1(&mut engine, legacy),
+            (2, _) => Engine::migrate_v2(&mut engine),
+            _ => {}
+        }
+        // deadlines are kept on the disputes, the index is rebuilt from them
+        engine.deadlines = engine
+            .transaction_status
+            .values()
+            .flat_map(|statuses| statuses.iter())
+            .flat_map(|(trans_id, status)| status.disputes.iter().filter_map(|dispute| dispute.deadline.map(|deadline| (deadline, *trans_id))))
+            .collect();
+        engine.clock = snapshot.clock;
+        engine.journal_seq = snapshot.journal_seq;
+        engine.ledger_seq = snapshot.ledger_seq;
+        engine.books = match snapshot.books {
+            Some(balances) => {
+                let defaults = balances.into_iter().map(|(account, balance)| ((None, account), balance));
+                let named = snapshot.currency_books.into_iter().map(|(currency, account, balance)| ((Some(currency), account), balance));
+                Books { balances: defaults.chain(named).collect() }
+            }
+            // the balances are all that is known about such a snapshot, they become the opening balances
+            None => Books::opening(&engine.accounts).ok_or_else(|| serde_json::Error::custom("account balances overflow the books"))?,
+        };
+        Ok(engine)
+    }
+
+    // version 1 only has the flags, an open dispute was of the whole amount and so was a chargeback
+    // its deadline was kept on the tx and moves to the dispute
+    // books and currency balances missing from it are handled where they are loaded
+    fn migrate_v1(engine: &mut Engine, legacy: LegacyTransactions) {
+        for (client, statuses) in engine.transaction_status.iter_mut() {
+            for (trans_id, status) in statuses.iter_mut() {
+                let deadline = legacy.transactions.get(client).and_then(|statuses| statuses.get(trans_id)).and_then(|status| status.dispute_deadline);
+                if status.dispute {
+                    status.disputes = vec![Dispute { id: 1, amount: status.amount, deadline }];
+                    status.disputed = status.amount;
+                    status.opened_disputes = 1;
+                }
+                if status.charged_back {
+                    status.charged_back_amount = status.amount;
+                }
+            }
+        }
+    }
+
+    // version 2 has no dispute ids, the open disputes are numbered oldest first
+    // the ids of the disputes settled before the snapshot are lost, the open ones are all that can still be named
+    fn migrate_v2(engine: &mut Engine) {
+        for status in engine.transaction_status.values_mut().flat_map(|statuses| statuses.values_mut()) {
+            for (id, dispute) in (1..).zip(status.disputes.iter_mut()) {
+                dispute.id = id;
+            }
+            status.opened_disputes = status.disputes.len() as u32;
+        }
+    }
+
+    // writes to a temporary file next to path and renames it over path
+    // a crash half way through leaves the previous snapshot in place
+    pub fn save_snapshot_file<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+
+        let file = File::create(&tmp_path)?;
+        let mut output = BufWriter::new(file);
+        self.save_snapshot(&mut output)?;
+        output.into_inner().map_err(|err| err.into_error())?.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load_snapshot_file<P: AsRef<Path>>(path: P, config: EngineConfig) -> Result<Engine, SnapshotError> {
+        Engine::load_snapshot(BufReader::new(File::open(path)?), config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{RejectReason, TxError};
+    use crate::test_support::transaction;
+    use crate::transaction::Transaction;
+
+    fn yesterday() -> Engine {
+        // client 1 has an open dispute and a balance in euros, client 2 is locked
+        let mut engine = Engine::new();
+        engine.apply(transaction("deposit", 1, 1, "10.0")).unwrap();
+        engine.apply(Transaction { currency: "EUR".parse().ok(), ..transaction("deposit", 1, 5, "3.0") }).unwrap();
+        engine.apply(transaction("deposit", 1, 2, "2.5")).unwrap();
+        engine.apply(transaction("dispute", 1, 2, "")).unwrap();
+        engine.apply(transaction("deposit", 2, 3, "1.0")).unwrap();
+        engine.apply(transaction("dispute", 2, 3, "")).unwrap();
+        engine.apply(transaction("chargeback", 2, 3, "")).unwrap();
+        engine
+    }
+
+    fn round_trip(engine: &Engine) -> Engine {
+        let mut snapshot = vec![];
+        engine.save_snapshot(&mut snapshot).unwrap();
+        Engine::load_snapshot(snapshot.as_slice(), EngineConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn test_snapshot_round_trip_keeps_state() {
+        let engine = yesterday();
+        let resumed = round_trip(&engine);
+
+        assert_eq!(resumed.accounts, engine.accounts);
+        assert_eq!(resumed.currency_accounts, engine.currency_accounts);
+        assert_eq!(resumed.transaction_status, engine.transaction_status);
+        assert_eq!(resumed.tx_clients, engine.tx_clients);
+        assert_eq!(resumed.ledger_seq, engine.ledger_seq);
+        assert_eq!(resumed.books, engine.books);
+        assert!(resumed.ledger().is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_resume_continues_where_it_left_off() {
+        let mut resumed = round_trip(&yesterday());
+
+        // today's file settles yesterday's dispute and cannot replay yesterday's ids
+        resumed.apply(transaction("resolve", 1, 2, "")).unwrap();
+        assert_eq!(resumed.apply(transaction("deposit", 3, 1, "1.0")), Err(TxError::Rejected(RejectReason::DuplicateTransaction)));
+        assert_eq!(resumed.apply(transaction("deposit", 2, 4, "1.0")), Err(TxError::Rejected(RejectReason::AccountLocked)));
+
+        assert_eq!(resumed.account(1).unwrap().available.to_string(), "12.5000");
+        assert!(resumed.account(2).unwrap().locked);
+    }
+
+    #[test]
+    fn test_snapshot_keeps_clock_and_deadlines() {
+        let config = EngineConfig { resolve_within: Some(60), ..Default::default() };
+        let mut engine = Engine::with_config(config.clone());
+        engine.apply(Transaction { timestamp: Some(100), ..transaction("deposit", 1, 1, "10.0") }).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "")).unwrap();
+
+        let mut snapshot = vec![];
+        engine.save_snapshot(&mut snapshot).unwrap();
+        let mut resumed = Engine::load_snapshot(snapshot.as_slice(), config).unwrap();
+        assert_eq!(resumed.clock(), Some(100));
+        assert_eq!(resumed.deadlines, engine.deadlines);
+
+        resumed.advance_clock(161);
+        assert_eq!(resumed.account(1).unwrap().available.to_string(), "10.0000");
+    }
+
+    #[test]
+    fn test_snapshot_migrates_version_1() {
+        // an open dispute and a chargeback as version 1 wrote them, without books
+        let snapshot = r#"{"version":1,"accounts":{"1":{"available":"5.0","held":"2.5","total":"7.5","locked":true}},
+            "transactions":{"1":{"2":{"amount":"2.5","deposit":true,"dispute":true},"3":{"amount":"1.0","deposit":true,"dispute":false,"charged_back":true}}},
+            "tx_clients":{"2":1,"3":1}}"#;
+        let mut engine = Engine::load_snapshot(snapshot.as_bytes(), EngineConfig::default()).unwrap();
+
+        let status = engine.transaction(1, 2).unwrap();
+        assert_eq!(status.disputes, vec![Dispute { id: 1, amount: "2.5".parse().unwrap(), deadline: None }]);
+        assert_eq!(status.disputed, "2.5".parse().unwrap());
+        assert_eq!(engine.transaction(1, 3).unwrap().charged_back_amount, "1.0".parse().unwrap());
+        assert_eq!(engine.check_books(), Ok(()));
+
+        engine.apply(transaction("resolve", 1, 2, "")).unwrap();
+        assert_eq!(engine.account(1).unwrap().available.to_string(), "7.5000");
+    }
+
+    #[test]
+    fn test_snapshot_migrates_version_1_deadlines() {
+        // an open dispute with a resolution deadline as version 1 wrote it, on the tx
+        let snapshot = r#"{"version":1,"accounts":{"1":{"available":"0.0","held":"10.0","total":"10.0","locked":false}},
+            "transactions":{"1":{"1":{"amount":"10.0","deposit":true,"dispute":true,"timestamp":100,"dispute_deadline":160}}},
+            "tx_clients":{"1":1},"clock":100}"#;
+        let config = EngineConfig { resolve_within: Some(60), ..Default::default() };
+        let mut engine = Engine::load_snapshot(snapshot.as_bytes(), config).unwrap();
+
+        assert_eq!(engine.transaction(1, 1).unwrap().disputes, vec![Dispute { id: 1, amount: "10.0".parse().unwrap(), deadline: Some(160) }]);
+        assert_eq!(engine.deadlines.len(), 1);
+
+        engine.advance_clock(161);
+        assert_eq!(engine.account(1).unwrap().available.to_string(), "10.0000");
+        assert!(engine.deadlines.is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_migrates_version_2() {
+        // two open disputes of the same amount as version 2 wrote them, without ids
+        let snapshot = r#"{"version":2,"accounts":{"1":{"available":"5.0","held":"5.0","total":"10.0","locked":false}},
+            "transactions":{"1":{"1":{"amount":"10.0","deposit":true,"dispute":true,"disputes":[{"amount":"2.5","deadline":50},{"amount":"2.5"}],"disputed":"5.0"}}},
+            "tx_clients":{"1":1},"clock":10}"#;
+        let mut engine = Engine::load_snapshot(snapshot.as_bytes(), EngineConfig::default()).unwrap();
+
+        let ids: Vec<u32> = engine.transaction(1, 1).unwrap().disputes.iter().map(|dispute| dispute.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+
+        // the second one is settled by its id, the next dispute does not reuse it
+        engine.apply(Transaction { dispute: Some(2), ..transaction("resolve", 1, 1, "") }).unwrap();
+        engine.apply(transaction("dispute", 1, 1, "1.0")).unwrap();
+        let status = engine.transaction(1, 1).unwrap();
+        assert_eq!(status.disputes.iter().map(|dispute| (dispute.id, dispute.deadline)).collect::<Vec<_>>(), vec![(1, Some(50)), (3, None)]);
+        assert_eq!(engine.deadlines.len(), 1);
+    }
+
+    #[test]
+    fn test_snapshot_is_deterministic() {
+        let mut first = vec![];
+        let mut second = vec![];
+        yesterday().save_snapshot(&mut first).unwrap();
+        round_trip(&yesterday()).save_snapshot(&mut second).unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_snapshot_rejects_other_versions() {
+        let snapshot = r#"{"version":5,"accounts":{},"transactions":{},"tx_clients":{}}"#;
+        let err = Engine::load_snapshot(snapshot.as_bytes(), EngineConfig::default()).unwrap_err();
+        assert!(matches!(err, SnapshotError::UnsupportedVersion(5)));
+
+        let err = Engine::load_snapshot("{}".as_bytes(), EngineConfig::default()).unwrap_err();
+        assert!(matches!(err, SnapshotError::Format(_)));
+    }
+}
@@ -0,0 +1,14 @@
+// fixtures shared by the unit tests
+
+use crate::transaction::Transaction;
+
+// a row with the columns every type has, an amount that does not parse, e.g. "", is left out
+pub(crate) fn transaction(trans_type: &str, client: u16, id: u32, amount: &str) -> Transaction {
+    Transaction {
+        trans_type: trans_type.to_string(),
+        client,
+        id,
+        amount: amount.parse().ok(),
+        ..Default::default()
+    }
+}
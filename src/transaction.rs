@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DefaultOnError};
+
+use crate::amount::{self, Amount};
+use crate::currency::Currency;
+
+// a single input row
+// deserializing is strict, a malformed field is an error
+// amount is empty for dispute, resolve and chargeback rows
+// currency is optional, as is its column, deposits and withdrawals without one use the account's default balance
+// destination is the client a transfer goes to, its column is only needed for transfers
+// reason is required for the admin freeze and unlock rows and ignored for the others
+// timestamp is optional, in seconds since the unix epoch, rows with one move the engine's clock forward
+// dispute is the id of the open dispute a resolve or chargeback settles, its column is optional and it is ignored for the other types
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transaction {
+    #[serde(rename = "type")]
+    pub trans_type: String,
+
+    pub client: u16,
+
+    #[serde(rename = "tx")]
+    pub id: u32,
+
+    pub amount: Option<Amount>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<u16>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dispute: Option<u32>,
+}
+
+// lenient stand-in for Transaction, kept for backwards compatibility
+// malformed fields are set to their default values and the row is ignored by the engine
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub(crate) struct LenientTransaction {
+    #[serde(rename = "type")]
+    #[serde_as(deserialize_as = "DefaultOnError")]
+    trans_type: String,
+
+    #[serde_as(deserialize_as = "DefaultOnError")]
+    client: u16,
+
+    #[serde(rename = "tx")]
+    #[serde_as(deserialize_as = "DefaultOnError")]
+    id: u32,
+
+    // amounts with more than four decimal places fail to parse and are treated as missing
+    #[serde(deserialize_with = "amount::deserialize_or_none")]
+    amount: Option<Amount>,
+
+    // not defaulted, a row with a malformed currency would otherwise move the default balance
+    #[serde(default)]
+    currency: Option<Currency>,
+
+    // not defaulted either, a transfer with a malformed destination is malformed rather than without one
+    #[serde(default)]
+    destination: Option<u16>,
+
+    // any text is a valid reason, there is nothing to default
+    #[serde(default)]
+    reason: Option<String>,
+
+    // not defaulted, a row with a malformed timestamp could otherwise escape a dispute window
+    #[serde(default)]
+    timestamp: Option<u64>,
+
+    // not defaulted, a resolve or chargeback with a malformed dispute id would otherwise settle the oldest dispute
+    #[serde(default)]
+    dispute: Option<u32>,
+}
+
+impl From<LenientTransaction> for Transaction {
+    fn from(lenient: LenientTransaction) -> Transaction {
+        Transaction {
+            trans_type: lenient.trans_type,
+            client: lenient.client,
+            id: lenient.id,
+            amount: lenient.amount,
+            currency: lenient.currency,
+            destination: lenient.destination,
+            reason: lenient.reason,
+            timestamp: lenient.timestamp,
+            dispute: lenient.dispute,
+        }
+    }
+}
+
+// known deposit/withdrawal/transfer kept for a future dispute/resolve/chargeback
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransactionStatus {
+    pub amount: Amount,
+    // chargebacks should only happen on a deposit if i understand correctly
+    pub deposit: bool,
+    // whether the tx has an open dispute, of all or part of its amount
+    pub dispute: bool,
+    // the open disputes, oldest first, missing from snapshots taken before partial disputes
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disputes: Vec<Dispute>,
+    // the amount the open disputes hold between them, what is neither disputed nor charged back can still be disputed
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub disputed: Amount,
+    // how many disputes have been opened on the tx, the next one gets the id after it
+    #[serde(default, skip_serializing_if = "is_zero_count")]
+    pub opened_disputes: u32,
+    // disputes move the balance of this currency, whatever the dispute row says
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    // the client a transfer went to, kept with the sender, None for deposits and withdrawals
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<u16>,
+    // the fee charged with a withdrawal, paid back once when the resolved disputes or the chargebacks add up to the whole withdrawal
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub fee: Amount,
+    // what the resolved disputes held between them, a part disputed and resolved twice counts twice
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub resolved_amount: Amount,
+    // the part of the amount charged back so far
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub charged_back_amount: Amount,
+    // a tx charged back in full is settled for good, it is kept for the record but cannot be disputed again
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub charged_back: bool,
+    // when the transaction happened, from its row, a tx without one can be disputed whatever the dispute window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+// one open dispute of a tx, a resolve or chargeback settles one of them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dispute {
+    // numbered from 1 per tx in the order the disputes were opened and never reused, a resolve or chargeback can name it
+    // missing from version 2 snapshots, which are numbered as they are loaded
+    #[serde(default)]
+    pub id: u32,
+    pub amount: Amount,
+    // when the engine settles it itself, only set when the config has resolve_within
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u64>,
+}
+
+fn is_zero(amount: &Amount) -> bool {
+    *amount == Amount::ZERO
+}
+
+fn is_zero_count(count: &u32) -> bool {
+    *count == 0
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}